@@ -0,0 +1,274 @@
+use crate::backend::{DrawCall, RecordingBackend, RenderBackend};
+use crate::canvas::{element_svg, save_pixmap, Canvas, RasterOptions};
+use resvg::tiny_skia::Pixmap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// How a drawing is split into frames
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationOptions {
+    /// Drawing steps (lines, arcs, fills, labels or clears) added per frame
+    pub steps_per_frame: usize,
+    /// Time each frame is shown for, in milliseconds
+    pub frame_delay: u16,
+    /// How png and gif frames are rasterised
+    pub raster: RasterOptions,
+}
+
+/// About how many frames an animation has when no steps per frame are given
+pub const DEFAULT_FRAMES: usize = 100;
+
+/// Steps per frame that animate `recording` in about `DEFAULT_FRAMES` frames
+pub fn default_steps_per_frame(recording: &RecordingBackend) -> usize {
+    recording.steps().div_ceil(DEFAULT_FRAMES).max(1)
+}
+
+/// The rasterised frame, kept from one frame to the next
+struct Frame {
+    pixmap: Pixmap,
+    /// Elements of the canvas already drawn on the pixmap
+    drawn: usize,
+    /// The canvas was wiped or its background changed, so start again
+    redraw: bool,
+}
+
+impl Frame {
+    /// Bring the pixmap up to date with the canvas, drawing only
+    /// the elements added since the last frame
+    fn update(&mut self, canvas: &Canvas, options: RasterOptions) -> Result<&Pixmap, String> {
+        if self.redraw {
+            self.pixmap.fill(resvg::tiny_skia::Color::TRANSPARENT);
+            canvas.render_elements(&mut self.pixmap, canvas.elements(), true, options)?;
+        } else if self.drawn < canvas.elements().len() {
+            let added = &canvas.elements()[self.drawn..];
+            canvas.render_elements(&mut self.pixmap, added, false, options)?;
+        }
+        self.drawn = canvas.elements().len();
+        self.redraw = false;
+        Ok(&self.pixmap)
+    }
+}
+
+/// Replay the recording one frame at a time, calling `on_frame`
+/// with the image as it looks at the end of each frame
+fn for_each_frame(
+    recording: &RecordingBackend,
+    options: AnimationOptions,
+    mut on_frame: impl FnMut(usize, &Pixmap) -> Result<(), String>,
+) -> Result<(), String> {
+    let (width, height) = recording.get_dimensions();
+    let mut canvas = Canvas::new(width, height);
+    let mut image = Frame {
+        pixmap: canvas.empty_pixmap(options.raster)?,
+        drawn: 0,
+        redraw: true,
+    };
+    let steps_per_frame = options.steps_per_frame.max(1);
+    let mut steps = 0;
+    let mut frame = 0;
+
+    for call in recording.calls.iter() {
+        call.replay(&mut canvas);
+        if matches!(call, DrawCall::Clear | DrawCall::SetBackground(_)) {
+            image.redraw = true;
+        }
+        if call.is_step() {
+            steps += 1;
+            if steps % steps_per_frame == 0 {
+                on_frame(frame, image.update(&canvas, options.raster)?)?;
+                frame += 1;
+            }
+        }
+    }
+    // the last frame is also where a drawing with no steps ends up
+    if steps % steps_per_frame != 0 || frame == 0 {
+        on_frame(frame, image.update(&canvas, options.raster)?)?;
+    }
+    Ok(())
+}
+
+/// Path of a numbered frame, e.g. `out.png` becomes `out_0003.png`
+pub fn frame_path(path: &Path, frame: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+    path.with_file_name(format!("{}_{:04}.png", stem, frame))
+}
+
+/// Save every frame as its own numbered png next to `path`
+pub fn save_png_frames(
+    recording: &RecordingBackend,
+    path: &Path,
+    options: AnimationOptions,
+) -> Result<(), String> {
+    for_each_frame(recording, options, |frame, pixmap| {
+        save_pixmap(pixmap, frame_path(path, frame), options.raster)
+    })
+}
+
+/// Save the frames as a looping animated gif
+pub fn save_gif(
+    recording: &RecordingBackend,
+    path: &Path,
+    options: AnimationOptions,
+) -> Result<(), String> {
+    let (width, height) = recording.get_dimensions();
+    let (width, height) = Canvas::new(width, height).raster_size(options.raster);
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err("gif images can be at most 65535 pixels wide or high".to_string()),
+    };
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(|e| e.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+
+    for_each_frame(recording, options, |_frame, pixmap| {
+        let mut rgba: Vec<u8> = Vec::with_capacity(pixmap.data().len());
+        for pixel in pixmap.pixels() {
+            let color = pixel.demultiply();
+            rgba.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+        }
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 10);
+        // gif delays are in hundredths of a second
+        frame.delay = options.frame_delay / 10;
+        encoder.write_frame(&frame).map_err(|e| e.to_string())
+    })
+}
+
+/// Write a self-contained svg where each element appears (and disappears,
+/// after CLEARSCREEN or CLEAN) at the time of its frame
+pub fn to_animated_svg(recording: &RecordingBackend, options: AnimationOptions) -> String {
+    let (width, height) = recording.get_dimensions();
+    let mut canvas = Canvas::new(width, height);
+    let steps_per_frame = options.steps_per_frame.max(1);
+    // the step each element was drawn in, and the step it was wiped in
+    let mut timeline: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut steps = 0;
+
+    for call in recording.calls.iter() {
+        if let DrawCall::Clear = call {
+            // keep the elements, but hide them from this step onwards
+            for (_, hidden) in timeline.iter_mut().filter(|(_, hidden)| hidden.is_none()) {
+                *hidden = Some(steps);
+            }
+        } else {
+            call.replay(&mut canvas);
+            while timeline.len() < canvas.elements().len() {
+                timeline.push((steps, None));
+            }
+        }
+        if call.is_step() {
+            steps += 1;
+        }
+    }
+
+    let seconds =
+        |step: usize| (step / steps_per_frame) as f32 * options.frame_delay as f32 / 1000.0;
+    let mut svg = canvas.svg_header();
+    for (element, (shown, hidden)) in canvas.elements().iter().zip(timeline.iter()) {
+        svg.push_str(&format!(
+            "  <g visibility=\"hidden\">\n  <set attributeName=\"visibility\" to=\"visible\" begin=\"{}s\" fill=\"freeze\"/>\n",
+            seconds(*shown)
+        ));
+        if let Some(hidden) = hidden {
+            svg.push_str(&format!(
+                "  <set attributeName=\"visibility\" to=\"hidden\" begin=\"{}s\" fill=\"freeze\"/>\n",
+                seconds(*hidden)
+            ));
+        }
+        svg.push_str(&element_svg(element));
+        svg.push_str("  </g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `lines` lines across a 20x20 drawing, one below the other
+    fn lines(lines: usize) -> RecordingBackend {
+        let mut recording = RecordingBackend::new(20, 20);
+        for line in 0..lines {
+            recording.move_to((0.0, line as f32));
+            recording.line_to((10.0, line as f32));
+        }
+        recording
+    }
+
+    fn options(steps_per_frame: usize) -> AnimationOptions {
+        AnimationOptions {
+            steps_per_frame,
+            frame_delay: 100,
+            raster: RasterOptions::default(),
+        }
+    }
+
+    /// When each element of an animated svg appears and disappears, in seconds
+    fn timings(svg: &str) -> Vec<(String, Option<String>)> {
+        let begin = |line: &str| {
+            line.split("begin=\"")
+                .nth(1)
+                .unwrap()
+                .split('"')
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        let mut timings = Vec::new();
+        for line in svg.lines() {
+            if line.contains("to=\"visible\"") {
+                timings.push((begin(line), None));
+            } else if line.contains("to=\"hidden\"") {
+                timings.last_mut().unwrap().1 = Some(begin(line));
+            }
+        }
+        timings
+    }
+
+    #[test]
+    fn animated_svg_shows_each_frame_of_steps_together() {
+        let svg = to_animated_svg(&lines(5), options(2));
+        let shown: Vec<String> = timings(&svg).into_iter().map(|(shown, _)| shown).collect();
+        assert_eq!(shown, ["0s", "0s", "0.1s", "0.1s", "0.2s"]);
+        assert_eq!(svg.matches("<g visibility=\"hidden\">").count(), 5);
+        assert!(svg.ends_with("  </g>\n</svg>\n"));
+    }
+
+    #[test]
+    fn animated_svg_hides_what_clearscreen_wipes() {
+        let mut recording = lines(2);
+        recording.clear();
+        recording.move_to((0.0, 5.0));
+        recording.line_to((10.0, 5.0));
+        let svg = to_animated_svg(&recording, options(1));
+        let expected = [
+            ("0s".to_string(), Some("0.2s".to_string())),
+            ("0.1s".to_string(), Some("0.2s".to_string())),
+            ("0.3s".to_string(), None),
+        ];
+        assert_eq!(timings(&svg), expected);
+    }
+
+    #[test]
+    fn png_frames_are_numbered_with_a_last_partial_frame() {
+        let dir = std::env::temp_dir().join(format!("rslogo-frames-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        save_png_frames(&lines(5), &dir.join("out.png"), options(2)).unwrap();
+        let mut frames: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        frames.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frames, ["out_0000.png", "out_0001.png", "out_0002.png"]);
+    }
+
+    #[test]
+    fn default_steps_give_about_a_hundred_frames() {
+        assert_eq!(default_steps_per_frame(&lines(0)), 1);
+        assert_eq!(default_steps_per_frame(&lines(100)), 1);
+        assert_eq!(default_steps_per_frame(&lines(250)), 3);
+    }
+}
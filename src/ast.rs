@@ -0,0 +1,131 @@
+use crate::commands::{Command, Statement};
+use crate::operations::Operation;
+use crate::procedures::DummyProcedure;
+use crate::utils::{extract_commands, AstArgs};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// How `rslogo ast` prints the program
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum AstFormat {
+    /// One node per line, indented under its parent
+    Tree,
+    /// The parsed program as JSON
+    Json,
+}
+
+/// A parsed file: its procedures by name, and the statements of the main program
+#[derive(Debug, Serialize)]
+pub struct Program<'a> {
+    pub procedures: BTreeMap<&'a str, &'a DummyProcedure>,
+    pub statements: &'a [Statement],
+}
+
+impl<'a> Program<'a> {
+    pub fn new(
+        statements: &'a [Statement],
+        procedures: &'a HashMap<String, DummyProcedure>,
+    ) -> Program<'a> {
+        Program {
+            procedures: procedures
+                .iter()
+                .map(|(name, procedure)| (name.as_str(), procedure))
+                .collect(),
+            statements,
+        }
+    }
+}
+
+fn push_line(out: &mut String, depth: usize, text: &str) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(text);
+    out.push('\n');
+}
+
+/// An operation with each operator above its two values
+fn operation_tree(operation: &Operation, depth: usize, out: &mut String) {
+    match (operation.operator(), operation) {
+        (Some((name, a, b)), _) => {
+            push_line(out, depth, name);
+            operation_tree(a, depth + 1, out);
+            operation_tree(b, depth + 1, out);
+        }
+        (None, _) => push_line(out, depth, &operation.to_string()),
+    }
+}
+
+fn statement_tree(statement: &Statement, depth: usize, out: &mut String) {
+    let command = &statement.command;
+    let mut head = format!("line {}: {}", statement.line, command.name());
+    match command {
+        Command::Make(name, _) | Command::Addassign(name, _) => {
+            head.push_str(&format!(" {}", name));
+        }
+        Command::Procedure(_, words) => {
+            for word in words.iter() {
+                head.push_str(&format!(" {}", word));
+            }
+        }
+        Command::Lsystem(lsystem) => {
+            head.push_str(&format!(" \"{}", lsystem.axiom));
+            for (from, to) in lsystem.rules.iter() {
+                head.push_str(&format!(" \"{}={}", from, to));
+            }
+        }
+        Command::Lsystemfile(path) => head.push_str(&format!(" \"{}", path)),
+        _ => {}
+    }
+    push_line(out, depth, &head);
+    match command {
+        Command::If(condition, block) | Command::Whlie(condition, block) => {
+            push_line(out, depth + 1, "condition");
+            operation_tree(condition, depth + 2, out);
+            push_line(out, depth + 1, "block");
+            for statement in block.iter() {
+                statement_tree(statement, depth + 2, out);
+            }
+        }
+        _ => {
+            for operation in command.operations() {
+                operation_tree(operation, depth + 1, out);
+            }
+        }
+    }
+}
+
+/// The program as an indented tree, procedures first
+pub fn to_tree(program: &Program) -> String {
+    let mut out = String::new();
+    for (name, procedure) in program.procedures.iter() {
+        let mut head = format!("TO {}", name);
+        for arg in procedure.args.iter() {
+            head.push_str(&format!(" {}", arg));
+        }
+        push_line(&mut out, 0, &head);
+        for statement in procedure.commands.iter() {
+            statement_tree(statement, 1, &mut out);
+        }
+    }
+    for statement in program.statements.iter() {
+        statement_tree(statement, 0, &mut out);
+    }
+    out
+}
+
+/// Parse a file and print what the parser made of it
+pub fn run_ast(args: AstArgs) -> Result<(), String> {
+    let source = std::fs::read_to_string(&args.file_path).map_err(|e| e.to_string())?;
+    let lines: Vec<&str> = source.lines().map(|line| line.trim()).collect();
+    let mut procedures: HashMap<String, DummyProcedure> = HashMap::new();
+    let (statements, _) = extract_commands(&lines, 0, &mut procedures)?;
+    let program = Program::new(&statements, &procedures);
+    match args.format {
+        AstFormat::Tree => print!("{}", to_tree(&program)),
+        AstFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&program).map_err(|e| e.to_string())?
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,555 @@
+use unsvg::Color;
+
+/// Pen and fill settings used for everything drawn after `set_style`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub color: Color,
+    pub width: f32,
+    pub pen_alpha: f32,
+    pub fill_alpha: f32,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            color: unsvg::COLORS[7],
+            width: 1.0,
+            pen_alpha: 1.0,
+            fill_alpha: 1.0,
+        }
+    }
+}
+
+/// Where the turtle is, reported to the backend before each drawing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TurtlePose {
+    pub position: (f32, f32),
+    pub heading: f32,
+    pub is_pen_down: bool,
+    /// Whether SHOWTURTLE has the turtle drawn at the end
+    pub is_shown: bool,
+}
+
+/// Something the executer can draw on.
+///
+/// The executer only talks to this trait, so new output formats
+/// don't need any changes to the interpreter.
+/// Points are in image coordinates, and angles are turtle headings:
+/// 0 degrees is straight up and angles go clockwise.
+pub trait RenderBackend {
+    /// Get the size of the drawing area as a tuple of (width, height)
+    fn get_dimensions(&self) -> (u32, u32);
+
+    fn set_style(&mut self, style: Style);
+
+    /// Lines and arcs drawn so far, including any since wiped.
+    /// Backends that don't keep count say 0.
+    fn segments(&self) -> usize {
+        0
+    }
+
+    /// Told where the turtle is after the next drawing call.
+    /// Only backends that record the turtle need to care.
+    fn set_turtle(&mut self, _turtle: TurtlePose) {}
+
+    fn set_background(&mut self, color: Color);
+
+    /// Wipe everything drawn so far
+    fn clear(&mut self);
+
+    /// Move the pen without drawing
+    fn move_to(&mut self, point: (f32, f32));
+
+    /// Draw a line from the current point
+    fn line_to(&mut self, point: (f32, f32));
+
+    /// Draw an arc of a circle, starting at `start` degrees and sweeping
+    /// clockwise by `sweep` degrees (anticlockwise if negative)
+    fn arc(&mut self, center: (f32, f32), radius: f32, start: f32, sweep: f32);
+
+    /// Fill a closed shape
+    fn fill(&mut self, points: &[(f32, f32)]);
+
+    /// Write text with its baseline starting at `point`
+    fn text(&mut self, point: (f32, f32), text: &str);
+}
+
+/// A single call made to a `RenderBackend`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCall {
+    SetStyle(Style),
+    SetTurtle(TurtlePose),
+    SetBackground(Color),
+    Clear,
+    MoveTo((f32, f32)),
+    LineTo((f32, f32)),
+    Arc {
+        center: (f32, f32),
+        radius: f32,
+        start: f32,
+        sweep: f32,
+    },
+    Fill(Vec<(f32, f32)>),
+    Text((f32, f32), String),
+}
+
+/// A backend that only remembers what it was asked to draw,
+/// so a drawing can be inspected or replayed onto another backend.
+#[derive(Debug, Clone)]
+pub struct RecordingBackend {
+    width: u32,
+    height: u32,
+    pub calls: Vec<DrawCall>,
+    segments: usize,
+}
+
+impl RecordingBackend {
+    pub fn new(width: u32, height: u32) -> RecordingBackend {
+        RecordingBackend {
+            width,
+            height,
+            calls: Vec::new(),
+            segments: 0,
+        }
+    }
+
+    /// Draw everything that was recorded onto `backend`
+    pub fn replay(&self, backend: &mut dyn RenderBackend) {
+        for call in self.calls.iter() {
+            call.replay(backend);
+        }
+    }
+
+    /// Number of calls that change what the drawing looks like
+    pub fn steps(&self) -> usize {
+        self.calls.iter().filter(|call| call.is_step()).count()
+    }
+}
+
+impl DrawCall {
+    /// Make this call on `backend`
+    pub fn replay(&self, backend: &mut dyn RenderBackend) {
+        match self {
+            DrawCall::SetStyle(style) => backend.set_style(*style),
+            DrawCall::SetTurtle(turtle) => backend.set_turtle(*turtle),
+            DrawCall::SetBackground(color) => backend.set_background(*color),
+            DrawCall::Clear => backend.clear(),
+            DrawCall::MoveTo(point) => backend.move_to(*point),
+            DrawCall::LineTo(point) => backend.line_to(*point),
+            DrawCall::Arc {
+                center,
+                radius,
+                start,
+                sweep,
+            } => backend.arc(*center, *radius, *start, *sweep),
+            DrawCall::Fill(points) => backend.fill(points),
+            DrawCall::Text(point, text) => backend.text(*point, text),
+        }
+    }
+
+    /// Whether this call changes what the drawing looks like,
+    /// which is what an animation shows one step at a time
+    pub fn is_step(&self) -> bool {
+        matches!(
+            self,
+            DrawCall::Clear
+                | DrawCall::LineTo(_)
+                | DrawCall::Arc { .. }
+                | DrawCall::Fill(_)
+                | DrawCall::Text(_, _)
+        )
+    }
+}
+
+impl RenderBackend for RecordingBackend {
+    fn get_dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn segments(&self) -> usize {
+        self.segments
+    }
+
+    fn set_style(&mut self, style: Style) {
+        self.calls.push(DrawCall::SetStyle(style));
+    }
+
+    fn set_turtle(&mut self, turtle: TurtlePose) {
+        self.calls.push(DrawCall::SetTurtle(turtle));
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.calls.push(DrawCall::SetBackground(color));
+    }
+
+    fn clear(&mut self) {
+        self.calls.push(DrawCall::Clear);
+    }
+
+    fn move_to(&mut self, point: (f32, f32)) {
+        self.calls.push(DrawCall::MoveTo(point));
+    }
+
+    fn line_to(&mut self, point: (f32, f32)) {
+        self.segments += 1;
+        self.calls.push(DrawCall::LineTo(point));
+    }
+
+    fn arc(&mut self, center: (f32, f32), radius: f32, start: f32, sweep: f32) {
+        self.segments += 1;
+        self.calls.push(DrawCall::Arc {
+            center,
+            radius,
+            start,
+            sweep,
+        });
+    }
+
+    fn fill(&mut self, points: &[(f32, f32)]) {
+        self.calls.push(DrawCall::Fill(points.to_vec()));
+    }
+
+    fn text(&mut self, point: (f32, f32), text: &str) {
+        self.calls.push(DrawCall::Text(point, text.to_string()));
+    }
+}
+
+/// The original `unsvg::Image` drawing, as a backend.
+///
+/// unsvg only draws straight lines at whole degrees on a black background, so arcs are drawn as short lines and fills as their outline,
+/// and backgrounds, text, widths and opacity are left out.
+#[derive(Clone)]
+pub struct UnsvgBackend {
+    pub image: unsvg::Image,
+    color: Color,
+    current: (f32, f32),
+}
+
+impl UnsvgBackend {
+    pub fn new(width: u32, height: u32) -> UnsvgBackend {
+        UnsvgBackend {
+            image: unsvg::Image::new(width, height),
+            color: Style::default().color,
+            current: (0.0, 0.0),
+        }
+    }
+}
+
+impl RenderBackend for UnsvgBackend {
+    fn get_dimensions(&self) -> (u32, u32) {
+        self.image.get_dimensions()
+    }
+
+    fn set_style(&mut self, style: Style) {
+        self.color = style.color;
+    }
+
+    fn set_background(&mut self, _color: Color) {}
+
+    fn clear(&mut self) {
+        let (width, height) = self.image.get_dimensions();
+        self.image = unsvg::Image::new(width, height);
+    }
+
+    fn move_to(&mut self, point: (f32, f32)) {
+        self.current = point;
+    }
+
+    fn line_to(&mut self, point: (f32, f32)) {
+        let (x, y) = self.current;
+        let (dx, dy) = (point.0 - x, point.1 - y);
+        // headings start straight up and go clockwise
+        let direction = dx.atan2(-dy).to_degrees();
+        let length = dx.hypot(dy);
+        // unsvg only fails on a line it can't make a path from, which has nothing to draw
+        let _ = self
+            .image
+            .draw_simple_line(x, y, direction.round() as i32, length, self.color);
+        self.current = point;
+    }
+
+    fn arc(&mut self, center: (f32, f32), radius: f32, start: f32, sweep: f32) {
+        let points = arc_points(center, radius, start, sweep);
+        self.move_to(points[0]);
+        for point in points[1..].iter() {
+            self.line_to(*point);
+        }
+    }
+
+    fn fill(&mut self, points: &[(f32, f32)]) {
+        let (first, rest) = match points.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        self.move_to(*first);
+        for point in rest.iter().chain(std::iter::once(first)) {
+            self.line_to(*point);
+        }
+    }
+
+    fn text(&mut self, _point: (f32, f32), _text: &str) {}
+}
+
+/// The point `length` pixels away from `point` towards `direction` degrees
+pub fn point_at(point: (f32, f32), direction: f32, length: f32) -> (f32, f32) {
+    let direction_rad = (direction - 90.0).to_radians();
+    (
+        point.0 + direction_rad.cos() * length,
+        point.1 + direction_rad.sin() * length,
+    )
+}
+
+/// Points along an arc (see `RenderBackend::arc`), about every 5 degrees,
+/// for outputs that can only draw straight lines
+pub fn arc_points(center: (f32, f32), radius: f32, start: f32, sweep: f32) -> Vec<(f32, f32)> {
+    let pieces = (sweep.abs() / 5.0).ceil().max(1.0) as usize;
+    (0..=pieces)
+        .map(|piece| point_at(center, start + sweep * piece as f32 / pieces as f32, radius))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executer::{execute_command, NoObserver, Observer};
+    use crate::procedures::DummyProcedure;
+    use crate::utils::{extract_commands, QueriesStruct};
+    use std::collections::HashMap;
+
+    /// Run a program on a 100x100 drawing, starting in the middle facing up
+    fn record(source: &str) -> Result<RecordingBackend, String> {
+        record_watched(source, &mut NoObserver)
+    }
+
+    fn record_watched(
+        source: &str,
+        observer: &mut dyn Observer,
+    ) -> Result<RecordingBackend, String> {
+        let lines: Vec<&str> = source.lines().map(|line| line.trim()).collect();
+        let mut procedures: HashMap<String, DummyProcedure> = HashMap::new();
+        let (statements, _) = extract_commands(&lines, 0, &mut procedures)?;
+        let mut variable_table = HashMap::new();
+        let mut queries = QueriesStruct::new(100, 100);
+        let mut image = RecordingBackend::new(100, 100);
+        for statement in statements.iter() {
+            execute_command(
+                &statement.command,
+                &mut variable_table,
+                &mut procedures,
+                false,
+                &mut HashMap::new(),
+                &mut queries,
+                &mut image,
+                observer,
+            )?;
+        }
+        Ok(image)
+    }
+
+    /// Where each line was drawn to
+    fn lines(image: &RecordingBackend) -> Vec<(f32, f32)> {
+        image
+            .calls
+            .iter()
+            .filter_map(|call| match call {
+                DrawCall::LineTo(point) => Some(*point),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pen_up_moves_without_drawing() {
+        let image = record("FORWARD \"10").unwrap();
+        assert_eq!(image.calls.last(), Some(&DrawCall::MoveTo((50.0, 40.0))));
+        assert!(lines(&image).is_empty());
+        assert_eq!(image.segments(), 0);
+    }
+
+    #[test]
+    fn pen_down_draws_a_line_from_the_turtle() {
+        let image = record("PENDOWN\nFORWARD \"10\nTURN \"90\nFORWARD \"20").unwrap();
+        let moves: Vec<&DrawCall> = image
+            .calls
+            .iter()
+            .filter(|call| matches!(call, DrawCall::MoveTo(_) | DrawCall::LineTo(_)))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                &DrawCall::MoveTo((50.0, 50.0)),
+                &DrawCall::LineTo((50.0, 40.0)),
+                &DrawCall::MoveTo((50.0, 40.0)),
+                &DrawCall::LineTo((70.0, 40.0)),
+            ]
+        );
+        assert_eq!(image.segments(), 2);
+    }
+
+    #[test]
+    fn lines_use_the_pen_colour() {
+        let image = record("PENDOWN\nSETPENCOLOR \"1\nBACK \"5").unwrap();
+        let style = image.calls.iter().find_map(|call| match call {
+            DrawCall::SetStyle(style) => Some(style),
+            _ => None,
+        });
+        assert_eq!(style.map(|style| style.color), Some(unsvg::COLORS[1]));
+        assert_eq!(lines(&image), [(50.0, 55.0)]);
+    }
+
+    #[test]
+    fn loops_and_procedures_draw_every_side() {
+        let source = "\
+            TO SIDE \"LENGTH
+              FORWARD :LENGTH
+              TURN \"90
+            END
+            PENDOWN
+            MAKE \"SIDES \"0
+            MAKE \"SIZE \"10
+            WHILE LT :SIDES \"4 [
+              SIDE :SIZE
+              ADDASSIGN \"SIDES \"1
+            ]";
+        let image = record(source).unwrap();
+        assert_eq!(
+            lines(&image),
+            [(50.0, 40.0), (60.0, 40.0), (60.0, 50.0), (50.0, 50.0)]
+        );
+    }
+
+    #[test]
+    fn clearscreen_wipes_the_drawing() {
+        let image = record("PENDOWN\nFORWARD \"10\nCLEARSCREEN\nFORWARD \"5").unwrap();
+        let clear = image.calls.iter().position(|call| *call == DrawCall::Clear);
+        assert!(clear.is_some());
+        assert_eq!(image.calls.last(), Some(&DrawCall::LineTo((50.0, 45.0))));
+        assert_eq!(image.steps(), 3);
+    }
+
+    #[test]
+    fn errors_stop_the_drawing() {
+        let result = record("PENDOWN\nFORWARD :MISSING");
+        assert!(result.is_err());
+    }
+
+    /// How many procedures have been entered and not exited
+    struct Depth(i32);
+
+    impl Observer for Depth {
+        fn enter_procedure(
+            &mut self,
+            _name: &str,
+            _args: &HashMap<String, String>,
+        ) -> Result<(), String> {
+            self.0 += 1;
+            Ok(())
+        }
+
+        fn exit_procedure(&mut self, _name: &str) {
+            self.0 -= 1;
+        }
+    }
+
+    #[test]
+    fn a_failing_procedure_is_still_exited() {
+        let mut depth = Depth(0);
+        let source = "TO INNER\n  FORWARD :MISSING\nEND\nTO OUTER\n  INNER\nEND\nOUTER";
+        assert!(record_watched(source, &mut depth).is_err());
+        assert_eq!(depth.0, 0);
+    }
+
+    #[test]
+    fn unsvg_backend_draws_each_line() {
+        let recording = record("PENDOWN\nFORWARD \"10\nTURN \"90\nFORWARD \"20").unwrap();
+        let mut image = UnsvgBackend::new(100, 100);
+        recording.replay(&mut image);
+        let path = std::env::temp_dir().join(format!("rslogo-unsvg-{}.svg", std::process::id()));
+        image.image.save_svg(&path).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the black background, then the two lines
+        assert_eq!(svg.matches("<path").count(), 3);
+        assert!(svg.contains("stroke=\"#ffffff\" d=\"M 50 40 L 70 40\""));
+    }
+
+    /// A quarter circle and a label, drawn straight onto a recording
+    fn arc_and_label() -> RecordingBackend {
+        let mut recording = RecordingBackend::new(100, 100);
+        recording.set_style(Style {
+            color: unsvg::COLORS[4],
+            ..Style::default()
+        });
+        recording.arc((50.0, 50.0), 20.0, 0.0, 90.0);
+        recording.text((10.0, 90.0), "a < b");
+        recording
+    }
+
+    #[test]
+    fn arcs_and_labels_reach_every_exporter() {
+        use crate::canvas::Canvas;
+        use crate::display_list::{DisplayItem, DisplayList};
+        use crate::document::{to_eps, to_pdf, PageOptions};
+        use crate::dxf::to_dxf;
+        use crate::plotter::{to_gcode, to_hpgl, PlotterOptions};
+        use crate::svg::to_compact_svg;
+
+        let recording = arc_and_label();
+
+        let mut canvas = Canvas::new(100, 100);
+        recording.replay(&mut canvas);
+        let svg = canvas.to_svg();
+        assert!(svg.contains(
+            "<path d=\"M 50 30 A 20 20 0 0 1 70 50\" fill=\"none\" stroke=\"#ff0000\"/>"
+        ));
+        assert!(svg.contains("fill=\"#ff0000\">a &lt; b</text>"));
+        let compact = to_compact_svg(&canvas, 2);
+        assert!(compact.contains("<path d=\"M50 30A20 20 0 0 1 70 50\""));
+        assert!(compact.contains(">a &lt; b</text>"));
+
+        let mut list = DisplayList::new(100, 100);
+        recording.replay(&mut list);
+        assert!(matches!(
+            &list.items[..],
+            [
+                DisplayItem::Arc { center: (50.0, 50.0), radius, sweep, .. },
+                DisplayItem::Label { at: (10.0, 90.0), text, .. },
+            ] if *radius == 20.0 && *sweep == 90.0 && text == "a < b"
+        ));
+
+        let page = PageOptions {
+            width: 100.0,
+            height: 100.0,
+        };
+        let pdf = to_pdf(&list, page);
+        assert!(pdf.contains("1 0 0 RG\n1 w\n50 30 m\n"));
+        assert!(pdf.contains("70 50 l\nS\n"));
+        assert!(pdf.contains("Tm (a < b) Tj ET"));
+        let eps = to_eps(&list, page);
+        assert!(eps.contains("newpath\n50 30 moveto\n"));
+        assert!(eps.contains("(a < b) show"));
+
+        let dxf = to_dxf(&list);
+        assert!(dxf.contains(
+            "100\nAcDbCircle\n10\n50\n20\n50\n30\n0\n40\n20\n100\nAcDbArc\n50\n0\n51\n90\n"
+        ));
+        assert!(dxf.contains("0\nTEXT\n"));
+        assert!(dxf.contains("\n1\na < b\n"));
+
+        // plotters have no text, and draw the arc as short lines
+        let options = PlotterOptions {
+            mm_per_pixel: 1.0,
+            feed_rate: 1000.0,
+        };
+        let hpgl = to_hpgl(&list, options);
+        let strokes: Vec<&str> = hpgl.lines().filter(|line| line.starts_with('P')).collect();
+        assert_eq!(strokes.len(), 3);
+        assert_eq!(strokes[0], "PU2000,2800;");
+        assert_eq!(strokes[1].split(',').count(), 18 * 2);
+        assert!(strokes[1].ends_with(",2800,2000;"));
+        let gcode = to_gcode(&list, options);
+        assert!(gcode.contains("G0 X50.000 Y70.000\n"));
+        assert!(gcode.contains("G1 X70.000 Y50.000 F1000\nG0 Z"));
+        assert_eq!(gcode.matches("G1 X").count(), 18);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::backend::{arc_points, point_at, RenderBackend, Style};
+use crate::svg::to_compact_svg;
+use resvg::usvg::{TreeParsing, TreeTextToPath};
+use resvg::{tiny_skia, usvg};
+use unsvg::Color;
+
+/// How a canvas is turned into pixels
+#[derive(Debug, Clone, Copy)]
+pub struct RasterOptions {
+    /// Pixels per unit of the drawing, so 2 gives an image twice as wide and high
+    pub scale: f32,
+    /// Smooth the edges of lines, shapes and text
+    pub antialias: bool,
+    /// Resolution recorded in png files, in dots per inch
+    pub dpi: Option<f32>,
+}
+
+impl Default for RasterOptions {
+    fn default() -> RasterOptions {
+        RasterOptions {
+            scale: 1.0,
+            antialias: true,
+            dpi: None,
+        }
+    }
+}
+
+/// A straight line drawn by the turtle
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub style: Style,
+}
+
+/// Part of a circle, see `RenderBackend::arc`
+#[derive(Debug, Clone)]
+pub struct Arc {
+    pub center: (f32, f32),
+    pub radius: f32,
+    pub start: f32,
+    pub sweep: f32,
+    pub style: Style,
+}
+
+/// A filled shape, such as the turtle sprite
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    pub points: Vec<(f32, f32)>,
+    pub style: Style,
+}
+
+/// A text label
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub point: (f32, f32),
+    pub text: String,
+    pub style: Style,
+}
+
+/// Everything that can be drawn on the canvas
+#[derive(Debug, Clone)]
+pub enum Element {
+    Line(Line),
+    Arc(Arc),
+    Polygon(Polygon),
+    Label(Label),
+}
+
+/// The image the turtle draws on, saved as svg or png.
+///
+/// This replaces `unsvg::Image` for saving files (`UnsvgBackend` still
+/// draws on one). unsvg always paints a black background
+/// and keeps what it draws in a private tree: there is no way to change
+/// its background or wipe it, which SETBACKGROUND, CLEARSCREEN and CLEAN
+/// need. The canvas keeps everything that was drawn instead, and writes
+/// the svg itself. That svg is laid out differently from unsvg's, but it
+/// draws the same picture: png output of programs without the new commands
+/// is pixel for pixel the same as before.
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    background: Color,
+    elements: Vec<Element>,
+    style: Style,
+    current: (f32, f32),
+    /// The part of the drawing shown, as (x, y, width, height)
+    view_box: (f32, f32, f32, f32),
+}
+
+impl Canvas {
+    /// Creates a canvas with a black background
+    pub fn new(width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            background: Color::black(),
+            elements: Vec::new(),
+            style: Style::default(),
+            current: (0.0, 0.0),
+            view_box: (0.0, 0.0, width as f32, height as f32),
+        }
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    pub fn background(&self) -> Color {
+        self.background
+    }
+
+    /// The smallest rectangle holding everything drawn,
+    /// as (min x, min y, max x, max y)
+    pub fn bounding_box(&self) -> Option<(f32, f32, f32, f32)> {
+        let mut points: Vec<(f32, f32)> = Vec::new();
+        for element in self.elements.iter() {
+            match element {
+                Element::Line(line) => {
+                    let half = line.style.width / 2.0;
+                    for (x, y) in [line.start, line.end] {
+                        points.push((x - half, y - half));
+                        points.push((x + half, y + half));
+                    }
+                }
+                Element::Arc(arc) => {
+                    let half = arc.style.width / 2.0;
+                    for (x, y) in arc_points(arc.center, arc.radius, arc.start, arc.sweep) {
+                        points.push((x - half, y - half));
+                        points.push((x + half, y + half));
+                    }
+                }
+                Element::Polygon(polygon) => points.extend(polygon.points.iter()),
+                Element::Label(label) => {
+                    // a rough guess at the size of 14px text
+                    let (x, y) = label.point;
+                    points.push((x, y - 14.0));
+                    points.push((x + 8.0 * label.text.chars().count() as f32, y + 4.0));
+                }
+            }
+        }
+
+        let first = *points.first()?;
+        Some(points.iter().fold(
+            (first.0, first.1, first.0, first.1),
+            |(min_x, min_y, max_x, max_y), (x, y)| {
+                (min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
+            },
+        ))
+    }
+
+    /// Zoom in or out so the drawing fills the image, leaving `margin` pixels
+    /// of the drawing's own size around it. The image size doesn't change.
+    pub fn fit(&mut self, margin: f32) {
+        if let Some((min_x, min_y, max_x, max_y)) = self.bounding_box() {
+            let mut view_width = max_x - min_x + 2.0 * margin;
+            let mut view_height = max_y - min_y + 2.0 * margin;
+            // grow the short side to match the image, so the background
+            // covers the whole image and the drawing stays centred
+            let aspect = self.width as f32 / self.height as f32;
+            if view_width / view_height < aspect {
+                view_width = view_height * aspect;
+            } else {
+                view_height = view_width / aspect;
+            }
+            self.view_box = (
+                (min_x + max_x - view_width) / 2.0,
+                (min_y + max_y - view_height) / 2.0,
+                view_width,
+                view_height,
+            );
+        }
+    }
+
+    /// Shrink (or grow) the image to the drawing plus `margin` pixels
+    pub fn crop(&mut self, margin: f32) {
+        if let Some((min_x, min_y, max_x, max_y)) = self.bounding_box() {
+            self.width = (max_x - min_x + 2.0 * margin).ceil().max(1.0) as u32;
+            self.height = (max_y - min_y + 2.0 * margin).ceil().max(1.0) as u32;
+            self.view_box = (
+                min_x - margin,
+                min_y - margin,
+                self.width as f32,
+                self.height as f32,
+            );
+        }
+    }
+
+    /// Render the canvas as an svg document
+    pub fn to_svg(&self) -> String {
+        let mut svg = self.svg_header();
+        for element in self.elements.iter() {
+            svg.push_str(&element_svg(element));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// The opening svg tag and the background, shared with animated svgs
+    pub fn svg_header(&self) -> String {
+        let (x, y, view_width, view_height) = self.view_box;
+        format!(
+            "{}  <rect x=\"{x}\" y=\"{y}\" width=\"{vw}\" height=\"{vh}\" fill=\"{}\"/>\n",
+            self.svg_open_tag(),
+            hex_color(self.background),
+            x = x,
+            y = y,
+            vw = view_width,
+            vh = view_height
+        )
+    }
+
+    fn svg_open_tag(&self) -> String {
+        let (x, y, view_width, view_height) = self.view_box;
+        format!(
+            "<svg width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\" xmlns=\"http://www.w3.org/2000/svg\">\n",
+            self.width, self.height, x, y, view_width, view_height
+        )
+    }
+
+    /// Save a compact svg, with coordinates rounded to `precision` decimal places
+    pub fn save_svg<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        precision: usize,
+    ) -> Result<(), String> {
+        std::fs::write(path, to_compact_svg(self, precision)).map_err(|e| e.to_string())
+    }
+
+    /// Size in pixels of the canvas once rasterised
+    pub fn raster_size(&self, options: RasterOptions) -> (u32, u32) {
+        (
+            (self.width as f32 * options.scale).ceil() as u32,
+            (self.height as f32 * options.scale).ceil() as u32,
+        )
+    }
+
+    /// An empty pixmap the size of the rasterised canvas
+    pub fn empty_pixmap(&self, options: RasterOptions) -> Result<tiny_skia::Pixmap, String> {
+        let (width, height) = self.raster_size(options);
+        tiny_skia::Pixmap::new(width, height)
+            .ok_or_else(|| "Cannot create an empty png".to_string())
+    }
+
+    /// Rasterise the svg document, so png and svg output always match
+    pub fn render_pixmap(&self, options: RasterOptions) -> Result<tiny_skia::Pixmap, String> {
+        let mut pixmap = self.empty_pixmap(options)?;
+        self.render_elements(&mut pixmap, &self.elements, true, options)?;
+        Ok(pixmap)
+    }
+
+    /// Draw `elements` over whatever is already on `pixmap`, starting with
+    /// the background if `background` is set. Animations use this to draw
+    /// only what each frame adds.
+    pub fn render_elements(
+        &self,
+        pixmap: &mut tiny_skia::Pixmap,
+        elements: &[Element],
+        background: bool,
+        options: RasterOptions,
+    ) -> Result<(), String> {
+        let mut svg = if background {
+            self.svg_header()
+        } else {
+            self.svg_open_tag()
+        };
+        for element in elements.iter() {
+            svg.push_str(&element_svg(element));
+        }
+        svg.push_str("</svg>\n");
+
+        let mut svg_options = usvg::Options::default();
+        if !options.antialias {
+            svg_options.shape_rendering = usvg::ShapeRendering::CrispEdges;
+            svg_options.text_rendering = usvg::TextRendering::OptimizeSpeed;
+        }
+        let mut tree = usvg::Tree::from_str(&svg, &svg_options).map_err(|e| e.to_string())?;
+        // loading fonts is slow, so only do it when there is text to draw
+        if elements
+            .iter()
+            .any(|element| matches!(element, Element::Label(_)))
+        {
+            tree.convert_text(&font_database());
+        }
+        let rtree = resvg::Tree::from_usvg(&tree);
+        rtree.render(
+            tiny_skia::Transform::from_scale(options.scale, options.scale),
+            &mut pixmap.as_mut(),
+        );
+        Ok(())
+    }
+
+    pub fn save_png<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        options: RasterOptions,
+    ) -> Result<(), String> {
+        save_pixmap(&self.render_pixmap(options)?, path, options)
+    }
+}
+
+/// Save a rasterised canvas as a png, recording its resolution if there is one
+pub fn save_pixmap<P: AsRef<std::path::Path>>(
+    pixmap: &tiny_skia::Pixmap,
+    path: P,
+    options: RasterOptions,
+) -> Result<(), String> {
+    let dpi = match options.dpi {
+        Some(dpi) => dpi,
+        None => return pixmap.save_png(path).map_err(|e| e.to_string()),
+    };
+
+    // tiny-skia can't record a resolution, so write the png ourselves
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(
+        std::io::BufWriter::new(file),
+        pixmap.width(),
+        pixmap.height(),
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: (dpi / 0.0254).round() as u32,
+        yppu: (dpi / 0.0254).round() as u32,
+        unit: png::Unit::Meter,
+    }));
+    let mut rgba: Vec<u8> = Vec::with_capacity(pixmap.data().len());
+    for pixel in pixmap.pixels() {
+        let color = pixel.demultiply();
+        rgba.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+    }
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgba).map_err(|e| e.to_string())
+}
+
+impl RenderBackend for Canvas {
+    fn get_dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.background = color;
+    }
+
+    /// Wipe everything drawn so far, keeping the background colour
+    fn clear(&mut self) {
+        self.elements.clear();
+    }
+
+    fn move_to(&mut self, point: (f32, f32)) {
+        self.current = point;
+    }
+
+    fn line_to(&mut self, point: (f32, f32)) {
+        self.elements.push(Element::Line(Line {
+            start: self.current,
+            end: point,
+            style: self.style,
+        }));
+        self.current = point;
+    }
+
+    fn arc(&mut self, center: (f32, f32), radius: f32, start: f32, sweep: f32) {
+        self.elements.push(Element::Arc(Arc {
+            center,
+            radius,
+            start,
+            sweep,
+            style: self.style,
+        }));
+    }
+
+    fn fill(&mut self, points: &[(f32, f32)]) {
+        self.elements.push(Element::Polygon(Polygon {
+            points: points.to_vec(),
+            style: self.style,
+        }));
+    }
+
+    fn text(&mut self, point: (f32, f32), text: &str) {
+        self.elements.push(Element::Label(Label {
+            point,
+            text: text.to_string(),
+            style: self.style,
+        }));
+    }
+}
+
+/// A single drawn element as an svg tag
+pub fn element_svg(element: &Element) -> String {
+    match element {
+        Element::Line(line) => format!(
+            "  <path d=\"M {} {} L {} {}\" fill=\"none\" {}/>\n",
+            line.start.0,
+            line.start.1,
+            line.end.0,
+            line.end.1,
+            stroke_attributes(&line.style)
+        ),
+        Element::Arc(arc) => format!(
+            "  <path d=\"{}\" fill=\"none\" {}/>\n",
+            arc_path(arc),
+            stroke_attributes(&arc.style)
+        ),
+        Element::Polygon(polygon) => {
+            let points: Vec<String> = polygon
+                .points
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, y))
+                .collect();
+            format!(
+                "  <polygon points=\"{}\" fill=\"{}\"{}/>\n",
+                points.join(" "),
+                hex_color(polygon.style.color),
+                opacity_attribute("fill-opacity", polygon.style.fill_alpha)
+            )
+        }
+        Element::Label(label) => format!(
+            "  <text x=\"{}\" y=\"{}\" font-family=\"sans-serif\" font-size=\"14\" fill=\"{}\"{}>{}</text>\n",
+            label.point.0,
+            label.point.1,
+            hex_color(label.style.color),
+            opacity_attribute("fill-opacity", label.style.pen_alpha),
+            escape_xml(&label.text)
+        ),
+    }
+}
+
+/// System fonts for rendering labels
+fn font_database() -> usvg::fontdb::Database {
+    let mut fontdb = usvg::fontdb::Database::new();
+    fontdb.load_system_fonts();
+    let sans_serif = usvg::fontdb::Query {
+        families: &[usvg::fontdb::Family::SansSerif],
+        ..Default::default()
+    };
+    if fontdb.query(&sans_serif).is_none() {
+        // fall back to any installed font rather than dropping the labels
+        let family = fontdb
+            .faces()
+            .find_map(|face| face.families.first())
+            .map(|family| family.0.clone());
+        if let Some(family) = family {
+            fontdb.set_sans_serif_family(family);
+        }
+    }
+    fontdb
+}
+
+/// Svg arcs can't tell which way to go round a full circle,
+/// so the arc is split into pieces of at most 180 degrees
+fn arc_path(arc: &Arc) -> String {
+    let (x, y) = point_at(arc.center, arc.start, arc.radius);
+    let mut path = format!("M {} {}", x, y);
+    let pieces = (arc.sweep.abs() / 180.0).ceil().max(1.0);
+    let sweep_flag = if arc.sweep > 0.0 { 1 } else { 0 };
+    for piece in 1..=(pieces as usize) {
+        let angle = arc.start + arc.sweep * piece as f32 / pieces;
+        let (x, y) = point_at(arc.center, angle, arc.radius);
+        path.push_str(&format!(
+            " A {} {} 0 0 {} {} {}",
+            arc.radius, arc.radius, sweep_flag, x, y
+        ));
+    }
+    path
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Format a colour as `#rrggbb`
+pub fn hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+}
+
+pub fn stroke_attributes(style: &Style) -> String {
+    let mut attributes = format!("stroke=\"{}\"", hex_color(style.color));
+    if style.width != 1.0 {
+        attributes.push_str(&format!(" stroke-width=\"{}\"", style.width));
+    }
+    attributes.push_str(&opacity_attribute("stroke-opacity", style.pen_alpha));
+    attributes
+}
+
+/// Opaque shapes leave out the opacity attribute to keep the svg small
+pub fn opacity_attribute(name: &str, alpha: f32) -> String {
+    if alpha < 1.0 {
+        format!(" {}=\"{}\"", name, alpha)
+    } else {
+        String::new()
+    }
+}
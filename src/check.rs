@@ -0,0 +1,752 @@
+use crate::commands::{Command, Statement};
+use crate::operations::Operation;
+use crate::parser::{parse_command, parse_operation};
+use crate::procedures::DummyProcedure;
+use crate::types::Inference;
+use crate::utils::{operation_length, CheckArgs, QueriesStruct};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How bad a problem is. Errors stop the program when it runs,
+/// warnings point at code that does nothing useful.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a program, with the text it is about.
+/// Lines and columns count from 1, and the length is in characters.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Words that can be used as values without a " or :
+const QUERIES: [&str; 4] = ["XCOR", "YCOR", "HEADING", "COLOR"];
+
+const OPERATORS: [&str; 10] = ["+", "-", "*", "/", "EQ", "NE", "AND", "OR", "GT", "LT"];
+
+/// Each word of a line, with the column it starts at
+pub fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    // column and byte offset of the word being read
+    let mut start: Option<(usize, usize)> = None;
+    for (column, (offset, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column, offset)),
+            (true, Some((first, first_offset))) => {
+                tokens.push((first + 1, &line[first_offset..offset]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((first, first_offset)) = start {
+        tokens.push((first + 1, &line[first_offset..]));
+    }
+    tokens
+}
+
+/// How many values a built in command takes, not counting MAKE and ADDASSIGN's name
+pub fn arity(keyword: &str) -> Option<usize> {
+    match keyword {
+        "PENUP" | "PENDOWN" | "SHOWTURTLE" | "HIDETURTLE" | "PUSHTURTLE" | "POPTURTLE"
+        | "CLEARSCREEN" | "CLEAN" => Some(0),
+        "FORWARD" | "BACK" | "RIGHT" | "LEFT" | "SETPENCOLOR" | "SETBACKGROUND" | "SETPENALPHA"
+        | "SETFILLALPHA" | "TURN" | "SETHEADING" | "SETX" | "SETY" | "MAKE" | "ADDASSIGN" => {
+            Some(1)
+        }
+        _ => None,
+    }
+}
+
+pub fn plural(count: usize, word: &str) -> String {
+    match count {
+        1 => format!("1 {}", word),
+        _ => format!("{} {}s", count, word),
+    }
+}
+
+/// Why a single word cannot be used as a value, if it cannot
+fn check_value(token: &str) -> Option<String> {
+    match token.chars().next() {
+        Some('"') => {
+            let word = &token[1..];
+            if word.parse::<f32>().is_ok() || word == "TRUE" || word == "FALSE" {
+                None
+            } else {
+                Some(format!("{} is not a number, TRUE or FALSE", token))
+            }
+        }
+        Some(':') => None,
+        _ if QUERIES.contains(&token) || OPERATORS.contains(&token) => None,
+        _ => Some(format!(
+            "{} is not a value: numbers start with \" and variables with :",
+            token
+        )),
+    }
+}
+
+/// The variables an operation reads, in order
+pub fn variables(operation: &Operation) -> Vec<&str> {
+    match (operation.operator(), operation) {
+        (Some((_, a, b)), _) => {
+            let mut found = variables(a);
+            found.extend(variables(b));
+            found
+        }
+        (None, Operation::Base(word)) if word.starts_with(':') => vec![word.as_str()],
+        (None, _) => Vec::new(),
+    }
+}
+
+/// Reads a whole program, carrying on past problems so they can all be reported
+pub struct Checker<'a> {
+    source: Vec<&'a str>,
+    diagnostics: Vec<Diagnostic>,
+    /// Procedures defined so far while parsing, as the parser sees them
+    procedures: HashMap<String, DummyProcedure>,
+    /// Every procedure in the file and the line of its TO
+    definitions: Vec<(String, usize, DummyProcedure)>,
+    /// The first TO line of each procedure name, including ones further down
+    declared: HashMap<String, usize>,
+    called: HashSet<String>,
+    /// Procedures whose body is being parsed
+    defining: Vec<String>,
+    /// Lines with a value of the wrong type
+    type_errors: HashSet<usize>,
+    /// Variables given a value by MAKE outside procedures, and where
+    globals: HashMap<String, usize>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(source: &'a str) -> Checker<'a> {
+        let source: Vec<&str> = source.lines().collect();
+        let mut declared = HashMap::new();
+        for (index, line) in source.iter().enumerate() {
+            let mut words = line.split_whitespace();
+            if let (Some("TO"), Some(name)) = (words.next(), words.next()) {
+                declared.entry(name.to_string()).or_insert(index + 1);
+            }
+        }
+        Checker {
+            source,
+            diagnostics: Vec::new(),
+            procedures: HashMap::new(),
+            definitions: Vec::new(),
+            declared,
+            called: HashSet::new(),
+            defining: Vec::new(),
+            type_errors: HashSet::new(),
+            globals: HashMap::new(),
+        }
+    }
+
+    fn report(&mut self, severity: Severity, line: usize, span: (usize, usize), message: String) {
+        self.diagnostics.push(Diagnostic {
+            line,
+            column: span.0,
+            length: span.1,
+            severity,
+            message,
+        });
+    }
+
+    fn error(&mut self, line: usize, span: (usize, usize), message: String) {
+        self.report(Severity::Error, line, span, message);
+    }
+
+    fn warning(&mut self, line: usize, span: (usize, usize), message: String) {
+        self.report(Severity::Warning, line, span, message);
+    }
+
+    /// Column and length of the whole line, without its indentation
+    fn line_span(&self, line: usize) -> (usize, usize) {
+        let tokens = tokens(self.source.get(line - 1).copied().unwrap_or_default());
+        match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => (first.0, last.0 + last.1.chars().count() - first.0),
+            _ => (1, 0),
+        }
+    }
+
+    /// Column and length of the first `word` on a line, or of the whole line
+    fn token_span(&self, line: usize, word: &str) -> (usize, usize) {
+        tokens(self.source.get(line - 1).copied().unwrap_or_default())
+            .into_iter()
+            .find(|(_, token)| *token == word)
+            .map_or_else(
+                || self.line_span(line),
+                |(column, token)| (column, token.chars().count()),
+            )
+    }
+
+    /// Column and length of the words of a line from the `index`th on
+    fn rest_span(&self, line: usize, index: usize) -> (usize, usize) {
+        let tokens = tokens(self.source.get(line - 1).copied().unwrap_or_default());
+        match (tokens.get(index), tokens.last()) {
+            (Some(first), Some(last)) => (first.0, last.0 + last.1.chars().count() - first.0),
+            _ => self.line_span(line),
+        }
+    }
+
+    /// Parse statements up to the end of the block opened by `opener`,
+    /// a keyword and its line, or to the end of the file
+    fn parse_block(&mut self, index: &mut usize, opener: Option<(&str, usize)>) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        while *index < self.source.len() {
+            let text = self.source[*index].trim();
+            let line = *index + 1;
+            *index += 1;
+            if text.starts_with("// ") || text.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = text.split_whitespace().collect();
+            match parts[0] {
+                "IF" | "WHILE" => {
+                    if parts[parts.len() - 1] != "[" {
+                        self.error(
+                            line,
+                            self.line_span(line),
+                            format!("{} needs a [ at the end of the line", parts[0]),
+                        );
+                        continue;
+                    }
+                    let condition = if parts.len() == 2 {
+                        self.error(
+                            line,
+                            self.token_span(line, parts[0]),
+                            format!("{} needs a condition", parts[0]),
+                        );
+                        None
+                    } else {
+                        self.operation(line, 1, &parts[1..parts.len() - 1])
+                    };
+                    let block = self.parse_block(index, Some((parts[0], line)));
+                    match (parts[0], condition) {
+                        ("IF", Some(condition)) => statements.push(Statement {
+                            line,
+                            command: Command::If(condition, block),
+                        }),
+                        (_, Some(condition)) => statements.push(Statement {
+                            line,
+                            command: Command::Whlie(condition, block),
+                        }),
+                        _ => {}
+                    }
+                }
+                "TO" => {
+                    self.defining.push(parts.get(1).unwrap_or(&"").to_string());
+                    let block = self.parse_block(index, Some(("TO", line)));
+                    self.defining.pop();
+                    if parts.len() < 2 {
+                        self.error(
+                            line,
+                            self.line_span(line),
+                            "TO needs a procedure name".to_string(),
+                        );
+                        continue;
+                    }
+                    for param in parts[2..].iter() {
+                        if !param.starts_with('"') {
+                            self.error(
+                                line,
+                                self.token_span(line, param),
+                                format!(
+                                    "parameter {} must start with \", as in \"{}",
+                                    param, param
+                                ),
+                            );
+                        }
+                    }
+                    if let Some((_, first, _)) = self
+                        .definitions
+                        .iter()
+                        .find(|(name, _, _)| name == parts[1])
+                    {
+                        self.warning(
+                            line,
+                            self.token_span(line, parts[1]),
+                            format!(
+                                "{} was already defined on line {}, and is replaced",
+                                parts[1], first
+                            ),
+                        );
+                    }
+                    let procedure = DummyProcedure {
+                        args: parts[2..].iter().map(|&s| s.to_string()).collect(),
+                        commands: block,
+                    };
+                    self.definitions
+                        .push((parts[1].to_string(), line, procedure.clone()));
+                    self.procedures.insert(parts[1].to_string(), procedure);
+                }
+                "]" | "END" => {
+                    let expected = match opener {
+                        Some(("TO", _)) => "END",
+                        Some(_) => "]",
+                        None => {
+                            let message = match parts[0] {
+                                "END" => "END without a TO: nothing after it is run",
+                                _ => "] without a matching [: nothing after it is run",
+                            };
+                            self.error(line, self.token_span(line, parts[0]), message.to_string());
+                            continue;
+                        }
+                    };
+                    if parts[0] != expected {
+                        let (keyword, first) = opener.expect("checked above");
+                        self.error(
+                            line,
+                            self.token_span(line, parts[0]),
+                            format!(
+                                "expected {} to close the {} on line {}",
+                                expected, keyword, first
+                            ),
+                        );
+                    }
+                    if parts.len() > 1 {
+                        self.error(
+                            line,
+                            self.rest_span(line, 1),
+                            format!("{} must be on a line of its own", parts[0]),
+                        );
+                    }
+                    return statements;
+                }
+                _ => {
+                    if let Some(command) = self.command(line, text, &parts) {
+                        statements.push(Statement { line, command });
+                    }
+                }
+            }
+        }
+        if let Some((keyword, first)) = opener {
+            let message = match keyword {
+                "TO" => format!("TO on line {} has no END", first),
+                _ => format!("the [ of this {} is never closed with ]", keyword),
+            };
+            let span = match keyword {
+                "TO" => self.token_span(first, "TO"),
+                _ => self.token_span(first, "["),
+            };
+            self.error(first, span, message);
+        }
+        statements
+    }
+
+    /// Parse the prefix expression made of `words`, which start at the `first`th word of the line
+    fn operation(&mut self, line: usize, first: usize, words: &[&str]) -> Option<Operation> {
+        for word in words.iter() {
+            if let Some(message) = check_value(word) {
+                self.error(line, self.token_span(line, word), message);
+                return None;
+            }
+        }
+        match crate::utils::extract_operations(words) {
+            Ok(operation) => Some(operation),
+            Err(e) => {
+                self.error(line, self.rest_span(line, first), e);
+                None
+            }
+        }
+    }
+
+    /// Parse a line that is not part of a block, explaining what is wrong with it if it does not parse
+    fn command(&mut self, line: usize, text: &str, parts: &[&str]) -> Option<Command> {
+        let keyword = parts[0];
+        if self.declared.contains_key(keyword) {
+            self.called.insert(keyword.to_string());
+        }
+        let error = match parse_command(text, &self.procedures) {
+            Ok(command) => return Some(command),
+            Err(e) => e,
+        };
+        if arity(keyword).is_none() {
+            if let Some(procedure) = self.procedures.get(keyword) {
+                let message = format!(
+                    "{} takes {}, found {}",
+                    keyword,
+                    plural(procedure.args.len(), "argument"),
+                    parts.len() - 1
+                );
+                self.error(line, self.line_span(line), message);
+            } else if ["LSYSTEM", "LSYSTEMFILE"].contains(&keyword) {
+                self.error(line, self.line_span(line), error);
+            } else {
+                let message = match self.declared.get(keyword) {
+                    _ if self.defining.iter().any(|name| name == keyword) => {
+                        format!(
+                            "{} cannot call itself, as it is not defined until its END",
+                            keyword
+                        )
+                    }
+                    Some(defined) if *defined > line => {
+                        format!(
+                            "{} is called before it is defined on line {}",
+                            keyword, defined
+                        )
+                    }
+                    _ => format!("unknown command {}", keyword),
+                };
+                self.error(line, self.token_span(line, keyword), message);
+            }
+            return None;
+        }
+
+        // a built in command that did not parse: say which argument is wrong
+        let expected = arity(keyword).expect("only built in commands get here");
+        let first = match keyword {
+            "MAKE" | "ADDASSIGN" => 2,
+            _ => 1,
+        };
+        if first == 2 && parts.len() < 2 {
+            self.error(
+                line,
+                self.token_span(line, keyword),
+                format!("{} needs a variable name and a value", keyword),
+            );
+            return None;
+        }
+        let words = &parts[first..];
+        for word in words.iter() {
+            if let Some(message) = check_value(word) {
+                self.error(line, self.token_span(line, word), message);
+                return None;
+            }
+        }
+        let mut found = 0;
+        let mut start = 0;
+        // the word where the values after the last one the command takes start
+        let mut extra: Option<usize> = None;
+        while start < words.len() {
+            match operation_length(&words[start..]) {
+                Some(length) => {
+                    if found == expected {
+                        extra.get_or_insert(start);
+                    }
+                    found += 1;
+                    start += length;
+                }
+                None => {
+                    self.error(
+                        line,
+                        self.rest_span(line, first + start),
+                        format!("{} is missing a value", words[start]),
+                    );
+                    return None;
+                }
+            }
+        }
+        let span = match extra {
+            Some(start) => self.rest_span(line, first + start),
+            None => self.token_span(line, keyword),
+        };
+        self.error(
+            line,
+            span,
+            format!(
+                "{} takes {}, found {}",
+                keyword,
+                plural(expected, "value"),
+                found
+            ),
+        );
+        None
+    }
+
+    /// Report variables used without a value, and constant values that cannot be worked out
+    fn check_operation(
+        &mut self,
+        line: usize,
+        operation: &Operation,
+        scope: &HashSet<String>,
+        procedure: Option<&str>,
+    ) {
+        for variable in variables(operation) {
+            self.check_variable(line, variable, scope, procedure);
+        }
+        if let Some(Err(e)) = self.constant(line, operation) {
+            self.error(line, self.rest_span(line, 1), e);
+        }
+    }
+
+    fn check_variable(
+        &mut self,
+        line: usize,
+        name: &str,
+        scope: &HashSet<String>,
+        procedure: Option<&str>,
+    ) {
+        if scope.contains(name) {
+            return;
+        }
+        let span = self.token_span(line, name);
+        let message = match (procedure, self.globals.get(name)) {
+            (Some(procedure), Some(_)) => format!(
+                "{} cannot be used inside {}, as it is made outside the procedure; pass it in as an argument",
+                name, procedure
+            ),
+            (None, Some(made)) if *made > line => {
+                format!("{} is used before it is made on line {}", name, made)
+            }
+            _ => format!("{} is not defined", name),
+        };
+        self.error(line, span, message);
+    }
+
+    /// The value of an operation that uses no variables or queries.
+    /// Lines with type errors are left alone, as working them out would fail.
+    fn constant(&self, line: usize, operation: &Operation) -> Option<Result<String, String>> {
+        fn is_constant(operation: &Operation) -> bool {
+            match operation.operator() {
+                Some((_, a, b)) => is_constant(a) && is_constant(b),
+                None => matches!(operation, Operation::Base(word) if word.starts_with('"')),
+            }
+        }
+        if is_constant(operation) && !self.type_errors.contains(&line) {
+            Some(parse_operation(
+                operation,
+                &HashMap::new(),
+                &QueriesStruct::new(0, 0),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Check a list of statements run one after the other.
+    /// `scope` holds the variables that have a value so far.
+    fn check_statements(
+        &mut self,
+        statements: &[Statement],
+        scope: &mut HashSet<String>,
+        procedure: Option<&str>,
+    ) {
+        let mut endless: Option<usize> = None;
+        for statement in statements.iter() {
+            let line = statement.line;
+            if let Some(first) = endless.take() {
+                self.warning(
+                    line,
+                    self.line_span(line),
+                    format!("unreachable: the WHILE on line {} never stops", first),
+                );
+            }
+            match &statement.command {
+                Command::Make(name, value) => {
+                    self.check_operation(line, value, scope, procedure);
+                    let key = name.replace('"', ":");
+                    if procedure.is_none() {
+                        self.globals.entry(key.clone()).or_insert(line);
+                    }
+                    scope.insert(key);
+                }
+                Command::Addassign(name, value) => {
+                    // ADDASSIGN always works on variables made outside procedures
+                    let key = name.replace('"', ":");
+                    let globals: HashSet<String> = self.globals.keys().cloned().collect();
+                    let defined = match procedure {
+                        Some(_) => globals.contains(&key),
+                        None => scope.contains(&key),
+                    };
+                    if !defined {
+                        let message = match self.globals.get(&key) {
+                            Some(made) if procedure.is_none() && *made > line => {
+                                format!("ADDASSIGN to {} before it is made on line {}", key, made)
+                            }
+                            _ => format!("ADDASSIGN to {}, which is never made with MAKE", key),
+                        };
+                        self.error(line, self.token_span(line, name), message);
+                    }
+                    let hidden = variables(value)
+                        .into_iter()
+                        .find(|variable| scope.contains(*variable) && !globals.contains(*variable));
+                    match hidden {
+                        Some(variable) => self.error(
+                            line,
+                            self.token_span(line, variable),
+                            format!(
+                                "ADDASSIGN only sees variables made outside procedures, so not {}",
+                                variable
+                            ),
+                        ),
+                        None => self.check_operation(line, value, &globals, None),
+                    }
+                }
+                Command::Procedure(name, params) => {
+                    self.called.insert(name.clone());
+                    for param in params.iter() {
+                        if param.starts_with(':') {
+                            self.check_variable(line, param, scope, procedure);
+                        } else {
+                            self.error(
+                                line,
+                                self.token_span(line, param),
+                                format!("arguments to {} must be variables, found {}", name, param),
+                            );
+                        }
+                    }
+                }
+                Command::If(condition, block) | Command::Whlie(condition, block) => {
+                    let keyword = statement.command.name().to_string();
+                    self.check_operation(line, condition, scope, procedure);
+                    match self.constant(line, condition).and_then(|value| value.ok()) {
+                        Some(value) if value != "TRUE" => {
+                            let hint = match condition {
+                                Operation::Base(_) => {
+                                    ": only EQ, NE, LT, GT, AND and OR give a condition that can be true"
+                                }
+                                _ => ", as its condition is always false",
+                            };
+                            self.warning(
+                                line,
+                                self.rest_span(line, 1),
+                                format!("unreachable: this {} never runs{}", keyword, hint),
+                            );
+                        }
+                        Some(_) if keyword == "WHILE" => endless = Some(line),
+                        _ => {}
+                    }
+                    // variables made inside the block might have a value afterwards
+                    self.check_statements(block, scope, procedure);
+                }
+                command => {
+                    for operation in command.operations() {
+                        self.check_operation(line, operation, scope, procedure);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Infer types in the parsed program, procedures first
+    fn infer(&self, statements: &[Statement]) -> Inference {
+        let mut inference = Inference::new();
+        for (name, _, procedure) in self.definitions.iter() {
+            inference.procedure(name, &procedure.args, &procedure.commands);
+        }
+        inference.program(statements);
+        inference
+    }
+
+    /// Parse as much of the program as possible and infer its types
+    pub fn types(mut self) -> Inference {
+        let mut index = 0;
+        let statements = self.parse_block(&mut index, None);
+        self.infer(&statements)
+    }
+
+    /// Check the whole program, returning every problem in the order they appear
+    pub fn check(mut self) -> Vec<Diagnostic> {
+        let mut index = 0;
+        let statements = self.parse_block(&mut index, None);
+
+        let inference = self.infer(&statements);
+        for error in inference.errors {
+            self.type_errors.insert(error.line);
+            self.error(
+                error.line,
+                self.token_span(error.line, &error.token),
+                error.message,
+            );
+        }
+
+        let mut scope = HashSet::new();
+        self.check_statements(&statements, &mut scope, None);
+
+        let definitions = std::mem::take(&mut self.definitions);
+        for (name, line, procedure) in definitions.iter() {
+            let mut scope: HashSet<String> = procedure
+                .args
+                .iter()
+                .map(|arg| arg.replace('"', ":"))
+                .collect();
+            self.check_statements(&procedure.commands, &mut scope, Some(name));
+            if !self.called.contains(name) {
+                self.warning(
+                    *line,
+                    self.token_span(*line, name),
+                    format!("procedure {} is never called", name),
+                );
+            }
+        }
+
+        let mut diagnostics = self.diagnostics;
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        diagnostics
+    }
+}
+
+/// Every problem that can be found in a program without running it
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    Checker::new(source).check()
+}
+
+/// A diagnostic as `file:line:column: error: message`, followed by the line it is about
+pub fn render(path: &str, source: &str, diagnostic: &Diagnostic, color: bool) -> String {
+    let label = match (diagnostic.severity, color) {
+        (Severity::Error, true) => "\x1b[31merror\x1b[0m".to_string(),
+        (Severity::Warning, true) => "\x1b[33mwarning\x1b[0m".to_string(),
+        (severity, false) => severity.to_string(),
+    };
+    let text = source.lines().nth(diagnostic.line - 1).unwrap_or_default();
+    let number = diagnostic.line.to_string();
+    let gutter = " ".repeat(number.len());
+    format!(
+        "{}:{}:{}: {}: {}\n{} |\n{} | {}\n{} | {}{}\n",
+        path,
+        diagnostic.line,
+        diagnostic.column,
+        label,
+        diagnostic.message,
+        gutter,
+        number,
+        text,
+        gutter,
+        " ".repeat(diagnostic.column - 1),
+        "^".repeat(diagnostic.length.max(1))
+    )
+}
+
+/// Check a file and print its problems, failing if there are errors
+pub fn run_check(args: CheckArgs) -> Result<(), String> {
+    let source = std::fs::read_to_string(&args.file_path).map_err(|e| e.to_string())?;
+    let path = args.file_path.display().to_string();
+    let color = std::env::var_os("NO_COLOR").is_none();
+    let diagnostics = check(&source);
+    for diagnostic in diagnostics.iter() {
+        println!("{}", render(&path, &source, diagnostic, color));
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 || (args.deny_warnings && warnings > 0) {
+        return Err(format!(
+            "{}: {} and {}",
+            path,
+            plural(errors, "error"),
+            plural(warnings, "warning")
+        ));
+    }
+    match warnings {
+        0 => println!("{}: no problems found", path),
+        _ => println!("{}: {}", path, plural(warnings, "warning")),
+    }
+    Ok(())
+}
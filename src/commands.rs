@@ -1,21 +1,136 @@
+use crate::lsystem::LSystem;
 use crate::operations::Operation;
+use serde::Serialize;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Command {
     Penup,
     Pendown,
+    Showturtle,
+    Hideturtle,
+    Pushturtle,
+    Popturtle,
+    Clearscreen,
+    Clean,
     Forward(Operation),
     Back(Operation),
     Left(Operation),
     Right(Operation),
     Setpencolor(Operation),
+    Setbackground(Operation),
+    Setpenalpha(Operation),
+    /// The opacity of filled shapes. Logo has no command to fill a shape,
+    /// so the only one it affects is the turtle drawn by SHOWTURTLE.
+    Setfillalpha(Operation),
     Turn(Operation),
     Setheading(Operation),
     Setx(Operation),
     Sety(Operation),
     Make(String, Operation),
     Addassign(String, Operation),
-    If(Operation, Vec<Command>),
-    Whlie(Operation, Vec<Command>),
+    If(Operation, Vec<Statement>),
+    Whlie(Operation, Vec<Statement>),
     Procedure(String, Vec<String>),
+    Lsystem(LSystem),
+    /// An L-system rule file, read when the command runs. The path is
+    /// relative to the program's file.
+    Lsystemfile(String),
+}
+
+impl Command {
+    /// The Logo keyword the command is written with, or the procedure's name
+    pub fn name(&self) -> &str {
+        match self {
+            Command::Penup => "PENUP",
+            Command::Pendown => "PENDOWN",
+            Command::Showturtle => "SHOWTURTLE",
+            Command::Hideturtle => "HIDETURTLE",
+            Command::Pushturtle => "PUSHTURTLE",
+            Command::Popturtle => "POPTURTLE",
+            Command::Clearscreen => "CLEARSCREEN",
+            Command::Clean => "CLEAN",
+            Command::Forward(_) => "FORWARD",
+            Command::Back(_) => "BACK",
+            Command::Left(_) => "LEFT",
+            Command::Right(_) => "RIGHT",
+            Command::Setpencolor(_) => "SETPENCOLOR",
+            Command::Setbackground(_) => "SETBACKGROUND",
+            Command::Setpenalpha(_) => "SETPENALPHA",
+            Command::Setfillalpha(_) => "SETFILLALPHA",
+            Command::Turn(_) => "TURN",
+            Command::Setheading(_) => "SETHEADING",
+            Command::Setx(_) => "SETX",
+            Command::Sety(_) => "SETY",
+            Command::Make(_, _) => "MAKE",
+            Command::Addassign(_, _) => "ADDASSIGN",
+            Command::If(_, _) => "IF",
+            Command::Whlie(_, _) => "WHILE",
+            Command::Procedure(name, _) => name,
+            Command::Lsystem(_) => "LSYSTEM",
+            Command::Lsystemfile(_) => "LSYSTEMFILE",
+        }
+    }
+
+    /// The expressions the command evaluates, in order
+    pub fn operations(&self) -> Vec<&Operation> {
+        match self {
+            Command::Forward(operation)
+            | Command::Back(operation)
+            | Command::Left(operation)
+            | Command::Right(operation)
+            | Command::Setpencolor(operation)
+            | Command::Setbackground(operation)
+            | Command::Setpenalpha(operation)
+            | Command::Setfillalpha(operation)
+            | Command::Turn(operation)
+            | Command::Setheading(operation)
+            | Command::Setx(operation)
+            | Command::Sety(operation)
+            | Command::Make(_, operation)
+            | Command::Addassign(_, operation)
+            | Command::If(operation, _)
+            | Command::Whlie(operation, _) => vec![operation],
+            Command::Lsystem(lsystem) => vec![&lsystem.iterations, &lsystem.angle, &lsystem.step],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The command written as one line of Logo. An IF or WHILE
+    /// gives its first line, up to the [ that opens its block.
+    pub fn source(&self) -> String {
+        let mut words = vec![self.name().to_string()];
+        match self {
+            Command::Make(name, value) | Command::Addassign(name, value) => {
+                words.push(name.clone());
+                words.push(value.to_string());
+            }
+            Command::Procedure(_, items) => {
+                words.extend(items.iter().cloned());
+            }
+            Command::Lsystem(lsystem) => {
+                words.push(format!("\"{}", lsystem.axiom));
+                words.extend(self.operations().iter().map(|operation| operation.to_string()));
+                words.extend(
+                    lsystem
+                        .rules
+                        .iter()
+                        .map(|(from, to)| format!("\"{}={}", from, to)),
+                );
+            }
+            Command::Lsystemfile(path) => words.push(format!("\"{}", path)),
+            Command::If(_, _) | Command::Whlie(_, _) => {
+                words.extend(self.operations().iter().map(|operation| operation.to_string()));
+                words.push("[".to_string());
+            }
+            _ => words.extend(self.operations().iter().map(|operation| operation.to_string())),
+        }
+        words.join(" ")
+    }
+}
+
+/// A command and the line of the file it was written on
+#[derive(Debug, Clone, Serialize)]
+pub struct Statement {
+    pub line: usize,
+    pub command: Command,
 }
@@ -0,0 +1,323 @@
+use crate::commands::Statement;
+use crate::executer::{Observer, Scope};
+use crate::session::{describe_variables, Session};
+use crate::utils::DebugArgs;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const HELP: &str = "\
+  s, step             run the next statement, going into blocks and procedures
+  n, next             run to the next statement, stepping over procedure calls
+  f, finish           run until the current procedure returns
+  c, continue         run until the next breakpoint
+  b, break LINE|NAME  pause at a line, or when a procedure is called
+  d, delete LINE|NAME remove a breakpoint
+  i, info             list breakpoints
+  p, print [:NAME]    print variables, or just one
+  bt, backtrace       show the procedures being run
+  t, turtle           show the turtle
+  l, list             show the code around the current line
+  q, quit             stop the program
+Pressing enter repeats the last command.";
+
+/// Where the debugger should pause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    Line(usize),
+    Procedure(String),
+}
+
+impl Breakpoint {
+    /// A line number, or otherwise a procedure name
+    pub fn parse(value: &str) -> Breakpoint {
+        match value.parse::<usize>() {
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => Breakpoint::Procedure(value.to_string()),
+        }
+    }
+}
+
+/// When to pause next
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Step,
+    /// At the next statement with at most this many procedures running
+    Next(usize),
+    /// At the next statement with fewer than this many procedures running
+    Finish(usize),
+    Continue,
+}
+
+/// A procedure being run, and the line it was called from
+struct Frame {
+    name: String,
+    line: usize,
+}
+
+/// Pauses the program to let someone look around, reading commands from the terminal
+pub struct Debugger {
+    source: Vec<String>,
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    stack: Vec<Frame>,
+    line: usize,
+    /// A procedure breakpoint was hit, so pause at its first statement
+    entered: Option<String>,
+    last_command: String,
+    editor: Option<DefaultEditor>,
+    /// Time spent waiting at the prompt, which limits don't count
+    paused: Duration,
+}
+
+impl Debugger {
+    /// A debugger that pauses before the first statement of `source`
+    pub fn new(source: &str, breakpoints: Vec<Breakpoint>) -> Debugger {
+        Debugger {
+            source: source.lines().map(|line| line.to_string()).collect(),
+            breakpoints,
+            mode: Mode::Step,
+            stack: Vec::new(),
+            line: 0,
+            entered: None,
+            last_command: "step".to_string(),
+            editor: None,
+            paused: Duration::ZERO,
+        }
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        self.source
+            .get(line.wrapping_sub(1))
+            .map(|text| text.trim())
+            .unwrap_or_default()
+    }
+
+    /// The current line, then each procedure call it is inside, innermost first
+    fn backtrace(&self) -> String {
+        let mut lines = format!("#0  line {} in {}\n", self.line, self.procedure_name());
+        for (depth, index) in (0..self.stack.len()).rev().enumerate() {
+            let caller = match index {
+                0 => "main",
+                _ => self.stack[index - 1].name.as_str(),
+            };
+            lines.push_str(&format!(
+                "#{}  line {} in {}\n",
+                depth + 1,
+                self.stack[index].line,
+                caller
+            ));
+        }
+        lines
+    }
+
+    fn procedure_name(&self) -> &str {
+        self.stack
+            .last()
+            .map_or("main", |frame| frame.name.as_str())
+    }
+
+    fn listing(&self) -> String {
+        let first = self.line.saturating_sub(3).max(1);
+        let last = (self.line + 3).min(self.source.len());
+        (first..=last)
+            .map(|line| {
+                let marker = if line == self.line { "=>" } else { "  " };
+                format!("{} {:4}  {}\n", marker, line, self.source_line(line))
+            })
+            .collect()
+    }
+
+    fn read_command(&mut self) -> Result<String, String> {
+        if self.editor.is_none() {
+            self.editor = Some(DefaultEditor::new().map_err(|e| e.to_string())?);
+        }
+        let editor = self.editor.as_mut().expect("the editor was just created");
+        match editor.readline("(debug) ") {
+            Ok(line) => {
+                if line.trim().is_empty() {
+                    return Ok(self.last_command.clone());
+                }
+                let _ = editor.add_history_entry(line.as_str());
+                self.last_command = line.trim().to_string();
+                Ok(self.last_command.clone())
+            }
+            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok("quit".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Read commands until one of them resumes the program
+    fn pause(&mut self, scope: &Scope) -> Result<(), String> {
+        loop {
+            let command = self.read_command()?;
+            let mut words = command.split_whitespace();
+            match (words.next().unwrap_or_default(), words.next()) {
+                ("s" | "step", _) => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                }
+                ("n" | "next", _) => {
+                    self.mode = Mode::Next(self.stack.len());
+                    return Ok(());
+                }
+                ("f" | "finish", _) => {
+                    if self.stack.is_empty() {
+                        println!("not in a procedure");
+                    } else {
+                        self.mode = Mode::Finish(self.stack.len());
+                        return Ok(());
+                    }
+                }
+                ("c" | "continue", _) => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                ("b" | "break", Some(value)) => {
+                    let breakpoint = Breakpoint::parse(value);
+                    println!("breakpoint at {}", describe_breakpoint(&breakpoint));
+                    if !self.breakpoints.contains(&breakpoint) {
+                        self.breakpoints.push(breakpoint);
+                    }
+                }
+                ("d" | "delete", Some(value)) => {
+                    let breakpoint = Breakpoint::parse(value);
+                    if self.breakpoints.contains(&breakpoint) {
+                        self.breakpoints.retain(|known| *known != breakpoint);
+                        println!("removed breakpoint at {}", describe_breakpoint(&breakpoint));
+                    } else {
+                        println!("no breakpoint at {}", describe_breakpoint(&breakpoint));
+                    }
+                }
+                ("b" | "break" | "d" | "delete", None) => {
+                    println!("usage: {} LINE|PROCEDURE", command.trim())
+                }
+                ("i" | "info", _) => {
+                    if self.breakpoints.is_empty() {
+                        println!("no breakpoints");
+                    }
+                    for breakpoint in self.breakpoints.iter() {
+                        println!("breakpoint at {}", describe_breakpoint(breakpoint));
+                    }
+                }
+                ("p" | "print", Some(name)) => {
+                    let key = format!(":{}", name.trim_start_matches([':', '"']));
+                    let value = scope
+                        .procedure_args
+                        .and_then(|args| args.get(&key))
+                        .or_else(|| scope.variable_table.get(&key));
+                    match value {
+                        Some(value) => println!("{} = {}", key, value),
+                        None => println!("{} is not defined", key),
+                    }
+                }
+                ("p" | "print", None) => {
+                    if let Some(args) = scope.procedure_args {
+                        println!("arguments of {}:", self.procedure_name());
+                        print!("{}", describe_variables(args));
+                    }
+                    println!("variables:");
+                    print!("{}", describe_variables(scope.variable_table));
+                }
+                ("bt" | "backtrace", _) => print!("{}", self.backtrace()),
+                ("t" | "turtle", _) => print!("{}", scope.queries.describe()),
+                ("l" | "list", _) => print!("{}", self.listing()),
+                ("q" | "quit", _) => return Err("stopped by the debugger".to_string()),
+                ("h" | "help", _) => println!("{}", HELP),
+                (command, _) => println!("unknown command {}, h for help", command),
+            }
+        }
+    }
+}
+
+fn describe_breakpoint(breakpoint: &Breakpoint) -> String {
+    match breakpoint {
+        Breakpoint::Line(line) => format!("line {}", line),
+        Breakpoint::Procedure(name) => format!("procedure {}", name),
+    }
+}
+
+impl Observer for Debugger {
+    fn before(&mut self, statement: &Statement, scope: &Scope) -> Result<(), String> {
+        self.line = statement.line;
+        let depth = self.stack.len();
+        let reason = if let Some(name) = self.entered.take() {
+            Some(format!("breakpoint at procedure {}, ", name))
+        } else if self.breakpoints.contains(&Breakpoint::Line(statement.line)) {
+            Some("breakpoint, ".to_string())
+        } else {
+            match self.mode {
+                Mode::Step => Some(String::new()),
+                Mode::Next(next) if depth <= next => Some(String::new()),
+                Mode::Finish(finish) if depth < finish => Some(String::new()),
+                _ => None,
+            }
+        };
+        let reason = match reason {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+        println!(
+            "{}line {} in {}: {}",
+            reason,
+            statement.line,
+            self.procedure_name(),
+            self.source_line(statement.line)
+        );
+        let started = Instant::now();
+        let result = self.pause(scope);
+        self.paused += started.elapsed();
+        result
+    }
+
+    fn enter_procedure(
+        &mut self,
+        name: &str,
+        _args: &HashMap<String, String>,
+    ) -> Result<(), String> {
+        self.stack.push(Frame {
+            name: name.to_string(),
+            line: self.line,
+        });
+        if self
+            .breakpoints
+            .contains(&Breakpoint::Procedure(name.to_string()))
+        {
+            self.entered = Some(name.to_string());
+        }
+        Ok(())
+    }
+
+    fn exit_procedure(&mut self, _name: &str) {
+        if let Some(frame) = self.stack.pop() {
+            self.line = frame.line;
+        }
+    }
+
+    fn take_paused(&mut self) -> Duration {
+        std::mem::take(&mut self.paused)
+    }
+}
+
+/// Run a file under the debugger, without saving an image
+pub fn run_debugger(args: DebugArgs) -> Result<(), String> {
+    let source = std::fs::read_to_string(&args.file_path).map_err(|e| e.to_string())?;
+    let breakpoints = args
+        .breakpoints
+        .iter()
+        .map(|value| Breakpoint::parse(value))
+        .collect();
+    let mut debugger = Debugger::new(&source, breakpoints);
+    let mut session = Session::new(args.width, args.height, unsvg::Color::black());
+    session.queries.source_dir = args
+        .file_path
+        .parent()
+        .map(|dir| dir.to_path_buf())
+        .unwrap_or_default();
+    let lines: Vec<&str> = source.lines().collect();
+    session.run_observed(&lines, &mut debugger)?;
+    println!("program finished");
+    print!("{}", session.queries.describe());
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::backend::{RenderBackend, Style, TurtlePose};
+use crate::canvas::hex_color;
+use serde::Serialize;
+use unsvg::Color;
+
+/// Colour, width and opacity of a drawn item
+#[derive(Debug, Clone, Serialize)]
+pub struct Pen {
+    pub color: String,
+    pub width: f32,
+    pub opacity: f32,
+}
+
+/// The turtle straight after an item was drawn
+#[derive(Debug, Clone, Serialize)]
+pub struct Turtle {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+    pub pen_down: bool,
+    pub shown: bool,
+}
+
+/// One thing drawn by the turtle
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DisplayItem {
+    Segment {
+        from: (f32, f32),
+        to: (f32, f32),
+        pen: Pen,
+        turtle: Turtle,
+    },
+    Arc {
+        center: (f32, f32),
+        radius: f32,
+        start: f32,
+        sweep: f32,
+        pen: Pen,
+        turtle: Turtle,
+    },
+    Fill {
+        points: Vec<(f32, f32)>,
+        pen: Pen,
+        turtle: Turtle,
+    },
+    Label {
+        at: (f32, f32),
+        text: String,
+        pen: Pen,
+        turtle: Turtle,
+    },
+}
+
+/// The drawing as data: everything still visible at the end of the program,
+/// in the order it was drawn.
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayList {
+    pub width: u32,
+    pub height: u32,
+    pub background: String,
+    pub items: Vec<DisplayItem>,
+    #[serde(skip)]
+    style: Style,
+    #[serde(skip)]
+    turtle: Option<TurtlePose>,
+    #[serde(skip)]
+    current: (f32, f32),
+}
+
+impl DisplayList {
+    pub fn new(width: u32, height: u32) -> DisplayList {
+        DisplayList {
+            width,
+            height,
+            background: hex_color(Color::black()),
+            items: Vec::new(),
+            style: Style::default(),
+            turtle: None,
+            current: (0.0, 0.0),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    pub fn save_json<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), String> {
+        std::fs::write(path, self.to_json()?).map_err(|e| e.to_string())
+    }
+
+    fn pen(&self, opacity: f32) -> Pen {
+        Pen {
+            color: hex_color(self.style.color),
+            width: self.style.width,
+            opacity,
+        }
+    }
+
+    fn turtle(&self) -> Turtle {
+        let turtle = self.turtle.unwrap_or(TurtlePose {
+            position: self.current,
+            heading: 0.0,
+            is_pen_down: false,
+            is_shown: false,
+        });
+        Turtle {
+            x: turtle.position.0,
+            y: turtle.position.1,
+            heading: turtle.heading,
+            pen_down: turtle.is_pen_down,
+            shown: turtle.is_shown,
+        }
+    }
+}
+
+impl RenderBackend for DisplayList {
+    fn get_dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    fn set_turtle(&mut self, turtle: TurtlePose) {
+        self.turtle = Some(turtle);
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.background = hex_color(color);
+    }
+
+    fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    fn move_to(&mut self, point: (f32, f32)) {
+        self.current = point;
+    }
+
+    fn line_to(&mut self, point: (f32, f32)) {
+        self.items.push(DisplayItem::Segment {
+            from: self.current,
+            to: point,
+            pen: self.pen(self.style.pen_alpha),
+            turtle: self.turtle(),
+        });
+        self.current = point;
+    }
+
+    fn arc(&mut self, center: (f32, f32), radius: f32, start: f32, sweep: f32) {
+        self.items.push(DisplayItem::Arc {
+            center,
+            radius,
+            start,
+            sweep,
+            pen: self.pen(self.style.pen_alpha),
+            turtle: self.turtle(),
+        });
+    }
+
+    fn fill(&mut self, points: &[(f32, f32)]) {
+        self.items.push(DisplayItem::Fill {
+            points: points.to_vec(),
+            pen: self.pen(self.style.fill_alpha),
+            turtle: self.turtle(),
+        });
+    }
+
+    fn text(&mut self, point: (f32, f32), text: &str) {
+        self.items.push(DisplayItem::Label {
+            at: point,
+            text: text.to_string(),
+            pen: self.pen(self.style.pen_alpha),
+            turtle: self.turtle(),
+        });
+    }
+}
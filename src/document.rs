@@ -0,0 +1,334 @@
+use crate::backend::arc_points;
+use crate::display_list::{DisplayItem, DisplayList, Pen};
+
+/// Points per millimetre
+pub const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+/// Page size of a printable document, in points.
+/// The drawing is scaled to fit the page and centred on it.
+#[derive(Debug, Clone, Copy)]
+pub struct PageOptions {
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Parse a page length such as `210mm`, `595pt` or `595` (points)
+pub fn parse_page_length(value: &str) -> Result<f32, String> {
+    let (number, scale) = match value.strip_suffix("mm") {
+        Some(number) => (number, POINTS_PER_MM),
+        None => (value.strip_suffix("pt").unwrap_or(value), 1.0),
+    };
+    match number.trim().parse::<f32>() {
+        Ok(number) if number > 0.0 => Ok(number * scale),
+        _ => Err(format!("invalid page length {}", value)),
+    }
+}
+
+/// Scale and offset that fit the drawing on the page, with y flipped
+/// because both PDF and PostScript have y going up
+fn page_transform(display_list: &DisplayList, page: PageOptions) -> [f32; 6] {
+    let scale = (page.width / display_list.width as f32).min(page.height / display_list.height as f32);
+    let offset_x = (page.width - display_list.width as f32 * scale) / 2.0;
+    let offset_y = (page.height - display_list.height as f32 * scale) / 2.0;
+    [scale, 0.0, 0.0, -scale, offset_x, page.height - offset_y]
+}
+
+/// `#rrggbb` as red, green and blue between 0 and 1
+fn rgb(hex: &str) -> (f32, f32, f32) {
+    let channel = |index: usize| {
+        u8::from_str_radix(hex.get(index..index + 2).unwrap_or("00"), 16).unwrap_or(0) as f32 / 255.0
+    };
+    (channel(1), channel(3), channel(5))
+}
+
+/// Escape a string for use inside `( )` in PDF and PostScript
+fn escape_string(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('(', "\\(")
+        .replace(')', "\\)")
+}
+
+fn polyline(points: &[(f32, f32)], move_to: &str, line_to: &str) -> String {
+    let mut path = String::new();
+    for (index, (x, y)) in points.iter().enumerate() {
+        let operator = if index == 0 { move_to } else { line_to };
+        path.push_str(&format!("{} {} {}\n", x, y, operator));
+    }
+    path
+}
+
+/// Write a single page PDF document
+pub fn to_pdf(display_list: &DisplayList, page: PageOptions) -> String {
+    // every distinct opacity needs its own graphics state resource
+    let mut opacities: Vec<f32> = vec![1.0];
+    let mut graphics_state = |opacity: f32| -> String {
+        let index = match opacities.iter().position(|known| *known == opacity) {
+            Some(index) => index,
+            None => {
+                opacities.push(opacity);
+                opacities.len() - 1
+            }
+        };
+        format!("/GS{} gs\n", index)
+    };
+
+    let [a, b, c, d, e, f] = page_transform(display_list, page);
+    let mut content = format!("{} {} {} {} {} {} cm\n", a, b, c, d, e, f);
+    let (red, green, blue) = rgb(&display_list.background);
+    content.push_str(&format!(
+        "{} {} {} rg\n0 0 {} {} re f\n",
+        red, green, blue, display_list.width, display_list.height
+    ));
+
+    let stroke_style = |pen: &Pen| {
+        let (red, green, blue) = rgb(&pen.color);
+        format!("{} {} {} RG\n{} w\n", red, green, blue, pen.width)
+    };
+    for item in display_list.items.iter() {
+        match item {
+            DisplayItem::Segment { from, to, pen, .. } => {
+                content.push_str(&graphics_state(pen.opacity));
+                content.push_str(&stroke_style(pen));
+                content.push_str(&polyline(&[*from, *to], "m", "l"));
+                content.push_str("S\n");
+            }
+            DisplayItem::Arc {
+                center,
+                radius,
+                start,
+                sweep,
+                pen,
+                ..
+            } => {
+                content.push_str(&graphics_state(pen.opacity));
+                content.push_str(&stroke_style(pen));
+                content.push_str(&polyline(&arc_points(*center, *radius, *start, *sweep), "m", "l"));
+                content.push_str("S\n");
+            }
+            DisplayItem::Fill { points, pen, .. } => {
+                let (red, green, blue) = rgb(&pen.color);
+                content.push_str(&graphics_state(pen.opacity));
+                content.push_str(&format!("{} {} {} rg\n", red, green, blue));
+                content.push_str(&polyline(points, "m", "l"));
+                content.push_str("h f\n");
+            }
+            DisplayItem::Label { at, text, pen, .. } => {
+                let (red, green, blue) = rgb(&pen.color);
+                content.push_str(&graphics_state(pen.opacity));
+                // flip the text back, as the page is drawn upside down
+                content.push_str(&format!(
+                    "{} {} {} rg\nBT /F1 14 Tf 1 0 0 -1 {} {} Tm ({}) Tj ET\n",
+                    red,
+                    green,
+                    blue,
+                    at.0,
+                    at.1,
+                    escape_string(text)
+                ));
+            }
+        }
+    }
+
+    let states: Vec<String> = opacities
+        .iter()
+        .enumerate()
+        .map(|(index, opacity)| format!("/GS{} << /CA {} /ca {} >>", index, opacity, opacity))
+        .collect();
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> /ExtGState << {} >> >> >>",
+            page.width,
+            page.height,
+            states.join(" ")
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets: Vec<usize> = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", index + 1, object));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    pdf
+}
+
+/// Write an encapsulated PostScript document.
+/// PostScript has no transparency, so opacity is ignored.
+pub fn to_eps(display_list: &DisplayList, page: PageOptions) -> String {
+    let mut eps = format!(
+        "%!PS-Adobe-3.0 EPSF-3.0\n%%BoundingBox: 0 0 {} {}\n%%Pages: 1\n%%EndComments\n",
+        page.width.ceil(),
+        page.height.ceil()
+    );
+    let [a, b, c, d, e, f] = page_transform(display_list, page);
+    eps.push_str(&format!("[{} {} {} {} {} {}] concat\n", a, b, c, d, e, f));
+    eps.push_str("1 setlinejoin\n/Helvetica findfont 14 scalefont setfont\n");
+    let (red, green, blue) = rgb(&display_list.background);
+    eps.push_str(&format!(
+        "{} {} {} setrgbcolor\n0 0 {} {} rectfill\n",
+        red, green, blue, display_list.width, display_list.height
+    ));
+
+    let stroke_style = |pen: &Pen| {
+        let (red, green, blue) = rgb(&pen.color);
+        format!("{} {} {} setrgbcolor\n{} setlinewidth\n", red, green, blue, pen.width)
+    };
+    for item in display_list.items.iter() {
+        match item {
+            DisplayItem::Segment { from, to, pen, .. } => {
+                eps.push_str(&stroke_style(pen));
+                eps.push_str("newpath\n");
+                eps.push_str(&polyline(&[*from, *to], "moveto", "lineto"));
+                eps.push_str("stroke\n");
+            }
+            DisplayItem::Arc {
+                center,
+                radius,
+                start,
+                sweep,
+                pen,
+                ..
+            } => {
+                eps.push_str(&stroke_style(pen));
+                eps.push_str("newpath\n");
+                eps.push_str(&polyline(
+                    &arc_points(*center, *radius, *start, *sweep),
+                    "moveto",
+                    "lineto",
+                ));
+                eps.push_str("stroke\n");
+            }
+            DisplayItem::Fill { points, pen, .. } => {
+                let (red, green, blue) = rgb(&pen.color);
+                eps.push_str(&format!("{} {} {} setrgbcolor\nnewpath\n", red, green, blue));
+                eps.push_str(&polyline(points, "moveto", "lineto"));
+                eps.push_str("closepath fill\n");
+            }
+            DisplayItem::Label { at, text, pen, .. } => {
+                let (red, green, blue) = rgb(&pen.color);
+                // flip the text back, as the page is drawn upside down
+                eps.push_str(&format!(
+                    "{} {} {} setrgbcolor\ngsave {} {} moveto 1 -1 scale ({}) show grestore\n",
+                    red,
+                    green,
+                    blue,
+                    at.0,
+                    at.1,
+                    escape_string(text)
+                ));
+            }
+        }
+    }
+    eps.push_str("showpage\n%%EOF\n");
+    eps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{RecordingBackend, RenderBackend, Style};
+
+    /// A red line, half see-through, and a label
+    fn drawing() -> DisplayList {
+        let mut recording = RecordingBackend::new(200, 100);
+        recording.set_style(Style {
+            color: unsvg::COLORS[4],
+            pen_alpha: 0.5,
+            ..Style::default()
+        });
+        recording.move_to((10.0, 10.0));
+        recording.line_to((190.0, 90.0));
+        recording.text((20.0, 50.0), "(a) b");
+        let mut display_list = DisplayList::new(200, 100);
+        recording.replay(&mut display_list);
+        display_list
+    }
+
+    const A4: PageOptions = PageOptions {
+        width: 595.0,
+        height: 842.0,
+    };
+
+    #[test]
+    fn pdf_cross_reference_points_at_each_object() {
+        let pdf = to_pdf(&drawing(), A4);
+        let xref = pdf.find("\nxref\n").unwrap() + 1;
+        let trailer = &pdf[xref + "xref\n".len()..];
+
+        let startxref: usize = trailer
+            .lines()
+            .skip_while(|line| *line != "startxref")
+            .nth(1)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(startxref, xref);
+
+        let mut lines = trailer.lines();
+        assert_eq!(lines.next(), Some("0 6"));
+        assert_eq!(lines.next(), Some("0000000000 65535 f "));
+        for object in 1..=5 {
+            let entry = lines.next().unwrap();
+            assert_eq!(entry.len(), 19, "entries are 20 bytes with the newline");
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", object)));
+        }
+        assert!(trailer.contains("<< /Size 6 /Root 1 0 R >>"));
+        assert!(pdf.ends_with("%%EOF\n"));
+    }
+
+    #[test]
+    fn pdf_stream_length_matches_its_content() {
+        let pdf = to_pdf(&drawing(), A4);
+        let start = pdf.find("<< /Length ").unwrap() + "<< /Length ".len();
+        let length: usize = pdf[start..].split(' ').next().unwrap().parse().unwrap();
+        let content_start = pdf.find("stream\n").unwrap() + "stream\n".len();
+        let content_end = pdf.find("endstream").unwrap();
+        assert_eq!(content_end - content_start, length);
+
+        // the drawing is fitted to the page width and centred, with y going up
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.contains("/MediaBox [0 0 595 842]"));
+        assert!(pdf.contains("stream\n2.975 0 0 -2.975 0 569.75 cm\n"));
+        // the half see-through pen gets its own graphics state
+        assert!(pdf.contains("/ExtGState << /GS0 << /CA 1 /ca 1 >> /GS1 << /CA 0.5 /ca 0.5 >> >>"));
+        assert!(pdf.contains("/GS1 gs\n1 0 0 RG\n1 w\n10 10 m\n190 90 l\nS\n"));
+        assert!(pdf.contains("(\\(a\\) b) Tj"));
+    }
+
+    #[test]
+    fn eps_has_a_bounding_box_and_draws_the_same_path() {
+        let eps = to_eps(&drawing(), A4);
+        assert!(eps.starts_with("%!PS-Adobe-3.0 EPSF-3.0\n%%BoundingBox: 0 0 595 842\n"));
+        assert!(eps.contains("newpath\n10 10 moveto\n190 90 lineto\nstroke\n"));
+        assert!(eps.contains("(\\(a\\) b) show"));
+        assert!(eps.ends_with("showpage\n%%EOF\n"));
+    }
+
+    #[test]
+    fn page_lengths_are_read_in_points_or_millimetres() {
+        assert_eq!(parse_page_length("595"), Ok(595.0));
+        assert_eq!(parse_page_length("595pt"), Ok(595.0));
+        assert!((parse_page_length("210mm").unwrap() - 595.2756).abs() < 0.001);
+        assert!(parse_page_length("-1").is_err());
+        assert!(parse_page_length("wide").is_err());
+    }
+}
@@ -0,0 +1,402 @@
+use crate::display_list::{DisplayItem, DisplayList};
+
+/// A run of joined segments waiting to be written
+struct Polyline {
+    layer: String,
+    points: Vec<(f32, f32)>,
+}
+
+/// Layer name for a pen colour, e.g. `PEN_ff0000`
+fn layer_name(color: &str) -> String {
+    format!("PEN_{}", color.trim_start_matches('#'))
+}
+
+/// A pen colour such as `#ff0000` as one number, which is how DXF stores true colour
+fn true_color(color: &str) -> i64 {
+    i64::from_str_radix(color.trim_start_matches('#'), 16).unwrap_or(0)
+}
+
+/// The first AutoCAD colour indexes, which every CAD program shows the same way
+const ACI_COLORS: [(u8, (i64, i64, i64)); 9] = [
+    (1, (255, 0, 0)),
+    (2, (255, 255, 0)),
+    (3, (0, 255, 0)),
+    (4, (0, 255, 255)),
+    (5, (0, 0, 255)),
+    (6, (255, 0, 255)),
+    (7, (255, 255, 255)),
+    (8, (128, 128, 128)),
+    (9, (192, 192, 192)),
+];
+
+/// The colour index closest to a pen colour such as `#ff0000`
+fn color_index(color: &str) -> u8 {
+    let rgb = true_color(color);
+    let (r, g, b) = (rgb >> 16 & 0xff, rgb >> 8 & 0xff, rgb & 0xff);
+    ACI_COLORS
+        .iter()
+        .min_by_key(|(_, (ar, ag, ab))| (r - ar).pow(2) + (g - ag).pow(2) + (b - ab).pow(2))
+        .map_or(7, |(index, _)| *index)
+}
+
+/// Gives out the handles every object in an AC1015 file needs
+struct Handles {
+    next: u32,
+}
+
+impl Handles {
+    fn next(&mut self) -> String {
+        self.next += 1;
+        format!("{:X}", self.next)
+    }
+}
+
+/// Write a DXF drawing for CAD programs, as AutoCAD 2000 (AC1015).
+/// Each pen colour gets its own layer, with the nearest colour index and
+/// the exact colour as true colour. Joined segments become LWPOLYLINE
+/// entities, lone segments LINE, and the y axis is flipped to go up.
+pub fn to_dxf(display_list: &DisplayList) -> String {
+    let height = display_list.height as f32;
+    let mut handles = Handles { next: 0 };
+    // the model space block record owns every entity, so it is known before they are written
+    let model_space = handles.next();
+    let paper_space = handles.next();
+    let mut entities = String::new();
+    let mut layers: Vec<String> = Vec::new();
+    let mut polyline: Option<Polyline> = None;
+
+    for item in display_list.items.iter() {
+        let color = match item {
+            DisplayItem::Segment { pen, .. }
+            | DisplayItem::Arc { pen, .. }
+            | DisplayItem::Fill { pen, .. }
+            | DisplayItem::Label { pen, .. } => &pen.color,
+        };
+        if !layers.contains(color) {
+            layers.push(color.clone());
+        }
+        let layer = layer_name(color);
+
+        if let DisplayItem::Segment { from, to, .. } = item {
+            match polyline.as_mut() {
+                Some(current) if current.layer == layer && current.points.last() == Some(from) => {
+                    current.points.push(*to);
+                }
+                _ => {
+                    if let Some(finished) = polyline.take() {
+                        let entity = polyline_entity(&finished, false, height);
+                        entities.push_str(&entity_start(&mut handles, &model_space, &entity));
+                    }
+                    polyline = Some(Polyline {
+                        layer,
+                        points: vec![*from, *to],
+                    });
+                }
+            }
+            continue;
+        }
+        if let Some(finished) = polyline.take() {
+            let entity = polyline_entity(&finished, false, height);
+            entities.push_str(&entity_start(&mut handles, &model_space, &entity));
+        }
+
+        let entity = match item {
+            DisplayItem::Arc {
+                center,
+                radius,
+                start,
+                sweep,
+                ..
+            } => {
+                // an ARC is a CIRCLE with a start and end angle added
+                let circle = format!(
+                    "100\nAcDbEntity\n8\n{}\n100\nAcDbCircle\n10\n{}\n20\n{}\n30\n0\n40\n{}\n",
+                    layer,
+                    center.0,
+                    height - center.1,
+                    radius
+                );
+                if sweep.abs() >= 360.0 {
+                    format!("CIRCLE\n{}", circle)
+                } else {
+                    // turtle headings go clockwise from straight up,
+                    // DXF angles go anticlockwise from the x axis
+                    let from = 90.0 - start;
+                    let to = 90.0 - start - sweep;
+                    let (first, last) = if *sweep > 0.0 { (to, from) } else { (from, to) };
+                    format!(
+                        "ARC\n{}100\nAcDbArc\n50\n{}\n51\n{}\n",
+                        circle,
+                        first.rem_euclid(360.0),
+                        last.rem_euclid(360.0)
+                    )
+                }
+            }
+            DisplayItem::Fill { points, .. } => {
+                let outline = Polyline {
+                    layer,
+                    points: points.clone(),
+                };
+                polyline_entity(&outline, true, height)
+            }
+            DisplayItem::Label { at, text, .. } => format!(
+                "TEXT\n100\nAcDbEntity\n8\n{}\n100\nAcDbText\n10\n{}\n20\n{}\n30\n0\n40\n14\n1\n{}\n100\nAcDbText\n",
+                layer,
+                at.0,
+                height - at.1,
+                text
+            ),
+            DisplayItem::Segment { .. } => continue,
+        };
+        entities.push_str(&entity_start(&mut handles, &model_space, &entity));
+    }
+    if let Some(finished) = polyline.take() {
+        let entity = polyline_entity(&finished, false, height);
+        entities.push_str(&entity_start(&mut handles, &model_space, &entity));
+    }
+
+    let mut tables = String::new();
+    // the layers draw with this line type, so it has to be defined
+    let ltype_table = handles.next();
+    tables.push_str(&table_start("LTYPE", &ltype_table, 1));
+    tables.push_str(&format!(
+        "0\nLTYPE\n5\n{}\n330\n{}\n100\nAcDbSymbolTableRecord\n100\nAcDbLinetypeTableRecord\n2\nCONTINUOUS\n70\n0\n3\nSolid line\n72\n65\n73\n0\n40\n0.0\n0\nENDTAB\n",
+        handles.next(),
+        ltype_table
+    ));
+    // layer 0 always exists, and the blocks are drawn on it
+    let layer_table = handles.next();
+    tables.push_str(&table_start("LAYER", &layer_table, layers.len() + 1));
+    let layer_zero = [(String::from("0"), 7, 0xffffff)];
+    let pen_layers = layers
+        .iter()
+        .map(|color| (layer_name(color), color_index(color), true_color(color)));
+    for (name, index, rgb) in layer_zero.into_iter().chain(pen_layers) {
+        tables.push_str(&format!(
+            "0\nLAYER\n5\n{}\n330\n{}\n100\nAcDbSymbolTableRecord\n100\nAcDbLayerTableRecord\n2\n{}\n70\n0\n62\n{}\n420\n{}\n6\nCONTINUOUS\n",
+            handles.next(),
+            layer_table,
+            name,
+            index,
+            rgb
+        ));
+    }
+    tables.push_str("0\nENDTAB\n");
+    let block_record_table = handles.next();
+    tables.push_str(&table_start("BLOCK_RECORD", &block_record_table, 2));
+    let mut blocks = String::new();
+    for (name, record) in [
+        ("*Model_Space", &model_space),
+        ("*Paper_Space", &paper_space),
+    ] {
+        tables.push_str(&format!(
+            "0\nBLOCK_RECORD\n5\n{}\n330\n{}\n100\nAcDbSymbolTableRecord\n100\nAcDbBlockTableRecord\n2\n{}\n",
+            record, block_record_table, name
+        ));
+        blocks.push_str(&format!(
+            "0\nBLOCK\n5\n{}\n330\n{}\n100\nAcDbEntity\n8\n0\n100\nAcDbBlockBegin\n2\n{}\n70\n0\n10\n0\n20\n0\n30\n0\n3\n{}\n1\n\n",
+            handles.next(),
+            record,
+            name,
+            name
+        ));
+        blocks.push_str(&format!(
+            "0\nENDBLK\n5\n{}\n330\n{}\n100\nAcDbEntity\n8\n0\n100\nAcDbBlockEnd\n",
+            handles.next(),
+            record
+        ));
+    }
+    tables.push_str("0\nENDTAB\n");
+    let dictionary = handles.next();
+
+    let mut dxf = format!(
+        "0\nSECTION\n2\nHEADER\n9\n$ACADVER\n1\nAC1015\n9\n$HANDSEED\n5\n{:X}\n9\n$INSUNITS\n70\n0\n0\nENDSEC\n",
+        handles.next + 1
+    );
+    dxf.push_str("0\nSECTION\n2\nTABLES\n");
+    dxf.push_str(&tables);
+    dxf.push_str("0\nENDSEC\n0\nSECTION\n2\nBLOCKS\n");
+    dxf.push_str(&blocks);
+    dxf.push_str("0\nENDSEC\n0\nSECTION\n2\nENTITIES\n");
+    dxf.push_str(&entities);
+    dxf.push_str("0\nENDSEC\n0\nSECTION\n2\nOBJECTS\n");
+    dxf.push_str(&format!(
+        "0\nDICTIONARY\n5\n{}\n330\n0\n100\nAcDbDictionary\n281\n1\n",
+        dictionary
+    ));
+    dxf.push_str("0\nENDSEC\n0\nEOF\n");
+    dxf
+}
+
+/// The start of a symbol table holding `count` entries
+fn table_start(name: &str, handle: &str, count: usize) -> String {
+    format!(
+        "0\nTABLE\n2\n{}\n5\n{}\n330\n0\n100\nAcDbSymbolTable\n70\n{}\n",
+        name, handle, count
+    )
+}
+
+/// Give an entity, written from its type name on, a handle and its owner
+fn entity_start(handles: &mut Handles, owner: &str, entity: &str) -> String {
+    let (kind, rest) = entity.split_once('\n').unwrap_or((entity, ""));
+    format!(
+        "0\n{}\n5\n{}\n330\n{}\n{}",
+        kind,
+        handles.next(),
+        owner,
+        rest
+    )
+}
+
+/// A lone segment is written as a LINE, anything longer as a LWPOLYLINE
+fn polyline_entity(polyline: &Polyline, closed: bool, height: f32) -> String {
+    if polyline.points.len() == 2 && !closed {
+        let (start, end) = (polyline.points[0], polyline.points[1]);
+        return format!(
+            "LINE\n100\nAcDbEntity\n8\n{}\n100\nAcDbLine\n10\n{}\n20\n{}\n30\n0\n11\n{}\n21\n{}\n31\n0\n",
+            polyline.layer,
+            start.0,
+            height - start.1,
+            end.0,
+            height - end.1
+        );
+    }
+    let mut entity = format!(
+        "LWPOLYLINE\n100\nAcDbEntity\n8\n{}\n100\nAcDbPolyline\n90\n{}\n70\n{}\n",
+        polyline.layer,
+        polyline.points.len(),
+        if closed { 1 } else { 0 }
+    );
+    for (x, y) in polyline.points.iter() {
+        entity.push_str(&format!("10\n{}\n20\n{}\n", x, height - y));
+    }
+    entity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{RenderBackend, Style};
+    use unsvg::Color;
+
+    fn pen(list: &mut DisplayList, red: u8, green: u8, blue: u8) {
+        list.set_style(Style {
+            color: Color::new_rgb(red, green, blue),
+            ..Style::default()
+        });
+    }
+
+    /// The group codes and values of one section, as pairs
+    fn section<'a>(dxf: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+        let lines: Vec<&str> = dxf.lines().collect();
+        let pairs: Vec<(&str, &str)> = lines.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        let start = pairs
+            .iter()
+            .position(|pair| *pair == ("2", name))
+            .expect("section is written");
+        let end = start
+            + pairs[start..]
+                .iter()
+                .position(|pair| *pair == ("0", "ENDSEC"))
+                .unwrap();
+        pairs[start + 1..end].to_vec()
+    }
+
+    #[test]
+    fn joined_segments_become_one_lwpolyline() {
+        let mut list = DisplayList::new(100, 100);
+        pen(&mut list, 255, 0, 0);
+        list.move_to((10.0, 10.0));
+        list.line_to((20.0, 10.0));
+        list.line_to((20.0, 30.0));
+        list.move_to((50.0, 50.0));
+        list.line_to((60.0, 50.0));
+        list.arc((50.0, 50.0), 10.0, 0.0, 90.0);
+
+        let dxf = to_dxf(&list);
+        assert!(dxf.starts_with("0\nSECTION\n2\nHEADER\n9\n$ACADVER\n1\nAC1015\n"));
+        let entities = section(&dxf, "ENTITIES");
+        let kinds: Vec<&str> = entities
+            .iter()
+            .filter(|(code, _)| *code == "0")
+            .map(|(_, kind)| *kind)
+            .collect();
+        assert_eq!(kinds, ["LWPOLYLINE", "LINE", "ARC"]);
+
+        // three points, not closed, with y flipped to go up
+        let polyline: Vec<(&str, &str)> = entities
+            .iter()
+            .skip_while(|(code, _)| *code != "90")
+            .take_while(|(code, _)| *code != "0")
+            .copied()
+            .collect();
+        assert_eq!(
+            polyline,
+            [
+                ("90", "3"),
+                ("70", "0"),
+                ("10", "10"),
+                ("20", "90"),
+                ("10", "20"),
+                ("20", "90"),
+                ("10", "20"),
+                ("20", "70")
+            ]
+        );
+        // a quarter turn clockwise from straight up ends pointing along the x axis
+        assert!(entities.contains(&("50", "0")));
+        assert!(entities.contains(&("51", "90")));
+        // every entity has its own handle and belongs to model space
+        let handles: Vec<&str> = entities
+            .iter()
+            .filter(|(code, _)| *code == "5")
+            .map(|(_, handle)| *handle)
+            .collect();
+        assert_eq!(handles.len(), 3);
+        assert!(handles.iter().all(|handle| *handle != "1"));
+        assert_eq!(
+            entities
+                .iter()
+                .filter(|pair| **pair == ("330", "1"))
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn each_pen_colour_gets_a_layer() {
+        let mut list = DisplayList::new(100, 100);
+        list.move_to((0.0, 0.0));
+        pen(&mut list, 255, 0, 0);
+        list.line_to((10.0, 0.0));
+        pen(&mut list, 0, 0, 250);
+        list.line_to((10.0, 10.0));
+        pen(&mut list, 255, 0, 0);
+        list.line_to((0.0, 10.0));
+
+        let dxf = to_dxf(&list);
+        let tables = section(&dxf, "TABLES");
+        let layers: Vec<(&str, &str)> = tables
+            .windows(10)
+            .filter(|pairs| pairs[0] == ("0", "LAYER"))
+            .map(|pairs| {
+                let field = |code| pairs.iter().find(|(c, _)| *c == code).unwrap().1;
+                (field("2"), field("62"))
+            })
+            .collect();
+        assert_eq!(
+            layers,
+            [("0", "7"), ("PEN_ff0000", "1"), ("PEN_0000fa", "5")]
+        );
+        assert!(tables.contains(&("420", "16711680")));
+        assert!(tables.contains(&("420", "250")));
+
+        let entities = section(&dxf, "ENTITIES");
+        let on_layers: Vec<&str> = entities
+            .iter()
+            .filter(|(code, _)| *code == "8")
+            .map(|(_, layer)| *layer)
+            .collect();
+        assert_eq!(on_layers, ["PEN_ff0000", "PEN_0000fa", "PEN_ff0000"]);
+    }
+}
@@ -1,11 +1,178 @@
-use crate::commands::Command;
+use crate::backend::{RenderBackend, Style, TurtlePose};
+use crate::commands::{Command, Statement};
+use crate::lsystem::LSystem;
 use crate::procedures::DummyProcedure;
 use crate::parser::{parse_operation, parse_boolean};
 use crate::utils::QueriesStruct;
-use unsvg::Image;
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// What an observer can see of the running program
+pub struct Scope<'a> {
+  pub variable_table: &'a HashMap<String, String>,
+  /// Arguments of the procedure being run, if any
+  pub procedure_args: Option<&'a HashMap<String, String>>,
+  pub queries: &'a QueriesStruct,
+  /// Lines and arcs drawn so far
+  pub segments: usize,
+}
+
+/// Something watching the program run, such as the debugger.
+/// Returning an error from a hook stops the program.
+pub trait Observer {
+  /// Called before each statement runs
+  fn before(&mut self, _statement: &Statement, _scope: &Scope) -> Result<(), String> {
+      Ok(())
+  }
+
+  /// Called after each statement has run
+  fn after(&mut self, _statement: &Statement, _scope: &Scope) -> Result<(), String> {
+      Ok(())
+  }
+
+  /// Called instead of `after` when a statement fails. An error inside a
+  /// procedure is seen by the failing statement, then by each call around it.
+  fn failed(&mut self, _statement: &Statement, _scope: &Scope, _error: &str) {}
+
+  /// Called when a procedure is called, with its arguments
+  fn enter_procedure(&mut self, _name: &str, _args: &HashMap<String, String>) -> Result<(), String> {
+      Ok(())
+  }
+
+  /// Called when a procedure has finished
+  fn exit_procedure(&mut self, _name: &str) {}
+
+  /// Called at the start of every time round a WHILE loop,
+  /// so even a loop with nothing in it can be watched
+  fn loop_iteration(&mut self) -> Result<(), String> {
+      Ok(())
+  }
+
+  /// Called for each symbol an LSYSTEM draws, with the lines and arcs
+  /// drawn so far, as one statement can draw a great many of them
+  fn drawing(&mut self, _segments: usize) -> Result<(), String> {
+      Ok(())
+  }
+
+  /// How long this observer has held the program up since it was last
+  /// asked, such as the debugger waiting at its prompt
+  fn take_paused(&mut self) -> Duration {
+      Duration::ZERO
+  }
+
+  /// Told that another observer held the program up for `duration`,
+  /// which is not time the program spent running
+  fn paused(&mut self, _duration: Duration) {}
+}
+
+/// Runs the program without watching it
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// Tell every observer in turn, and after each one tell all of them
+/// how long it held the program up for
+fn tell_each(
+  observers: &mut [Box<dyn Observer>],
+  mut tell: impl FnMut(&mut dyn Observer) -> Result<(), String>,
+) -> Result<(), String> {
+  for index in 0..observers.len() {
+      let result = tell(observers[index].as_mut());
+      let paused = observers[index].take_paused();
+      if !paused.is_zero() {
+          observers.iter_mut().for_each(|observer| observer.paused(paused));
+      }
+      result?;
+  }
+  Ok(())
+}
+
+/// Several observers watching at once, each told in turn
+impl Observer for Vec<Box<dyn Observer>> {
+  fn before(&mut self, statement: &Statement, scope: &Scope) -> Result<(), String> {
+      tell_each(self, |observer| observer.before(statement, scope))
+  }
+
+  fn after(&mut self, statement: &Statement, scope: &Scope) -> Result<(), String> {
+      tell_each(self, |observer| observer.after(statement, scope))
+  }
+
+  fn failed(&mut self, statement: &Statement, scope: &Scope, error: &str) {
+      self.iter_mut().for_each(|observer| observer.failed(statement, scope, error))
+  }
+
+  fn enter_procedure(&mut self, name: &str, args: &HashMap<String, String>) -> Result<(), String> {
+      tell_each(self, |observer| observer.enter_procedure(name, args))
+  }
+
+  fn exit_procedure(&mut self, name: &str) {
+      self.iter_mut().for_each(|observer| observer.exit_procedure(name))
+  }
+
+  fn loop_iteration(&mut self) -> Result<(), String> {
+      tell_each(self, |observer| observer.loop_iteration())
+  }
+
+  fn drawing(&mut self, segments: usize) -> Result<(), String> {
+      tell_each(self, |observer| observer.drawing(segments))
+  }
+
+  fn take_paused(&mut self) -> Duration {
+      self.iter_mut().map(|observer| observer.take_paused()).sum()
+  }
+
+  fn paused(&mut self, duration: Duration) {
+      self.iter_mut().for_each(|observer| observer.paused(duration))
+  }
+}
+
+/// execute a statement, letting the observer see it before and after,
+/// or see the error if it fails
+#[allow(clippy::too_many_arguments)]
+pub fn execute_statement(
+  statement: &Statement,
+  variable_table: &mut HashMap<String, String>,
+  dummy_procedures: &mut HashMap<String, DummyProcedure>,
+  is_in_procedure: bool,
+  procedure_args: &mut HashMap<String, String>,
+  queries: &mut QueriesStruct,
+  image: &mut dyn RenderBackend,
+  observer: &mut dyn Observer,
+) -> Result<(), String> {
+  let scope = Scope {
+      variable_table,
+      procedure_args: if is_in_procedure { Some(procedure_args) } else { None },
+      queries,
+      segments: image.segments(),
+  };
+  observer.before(statement, &scope)?;
+  let result = execute_command(
+      &statement.command,
+      variable_table,
+      dummy_procedures,
+      is_in_procedure,
+      procedure_args,
+      queries,
+      image,
+      observer,
+  );
+  let scope = Scope {
+      variable_table,
+      procedure_args: if is_in_procedure { Some(procedure_args) } else { None },
+      queries,
+      segments: image.segments(),
+  };
+  match result {
+      Ok(()) => observer.after(statement, &scope),
+      Err(e) => {
+          observer.failed(statement, &scope, &e);
+          Err(e)
+      }
+  }
+}
 
 /// execute the code
+#[allow(clippy::too_many_arguments)]
 pub fn execute_command(
   command: &Command,
   variable_table: &mut HashMap<String, String>,
@@ -13,7 +180,8 @@ pub fn execute_command(
   is_in_procedure: bool,
   procedure_args: &mut HashMap<String, String>,
   queries: &mut QueriesStruct,
-  image: &mut Image,
+  image: &mut dyn RenderBackend,
+  observer: &mut dyn Observer,
 ) -> Result<(), String> {
   match command {
       Command::Penup => {
@@ -22,6 +190,28 @@ pub fn execute_command(
       Command::Pendown => {
           queries.is_pen_down = "TRUE".to_string();
       }
+      Command::Showturtle => {
+          queries.is_turtle_shown = "TRUE".to_string();
+      }
+      Command::Hideturtle => {
+          queries.is_turtle_shown = "FALSE".to_string();
+      }
+      Command::Pushturtle => {
+          queries.push_turtle();
+      }
+      Command::Popturtle => {
+          queries.pop_turtle()?;
+      }
+      Command::Clearscreen => {
+          let (width, height) = image.get_dimensions();
+          image.clear();
+          queries.xcor = format!("{}{}", "\"", (width / 2));
+          queries.ycor = format!("{}{}", "\"", (height / 2));
+          queries.heading = "\"0".to_string();
+      }
+      Command::Clean => {
+          image.clear();
+      }
       Command::Forward(numpixels)
       | Command::Back(numpixels)
       | Command::Right(numpixels)
@@ -31,110 +221,21 @@ pub fn execute_command(
           } else {
               variable_table
           };
-          let is_pen_down = parse_boolean(&queries.is_pen_down)?;
-          let x = queries.xcor[1..]
-              .parse::<f32>()
-              .expect("cannot parse as x coordinate");
-          let y = queries.ycor[1..]
-              .parse::<f32>()
-              .expect("cannot parse as y coordinate");
           let direction = queries.heading[1..]
-              .parse::<i32>()
+              .parse::<f32>()
               .expect("cannot parse as direction");
           let result = parse_operation(numpixels, table, queries)?;
           let length = result[1..].parse::<f32>().expect("cannot parse as length");
-          match command {
-              Command::Forward(_numpixels) => {
-                  if is_pen_down {
-                      let color_index: usize =
-                          queries.color[1..].parse::<usize>().expect("Invalid color");
-                      let (new_x, new_y) = Image::draw_simple_line(
-                          image,
-                          x,
-                          y,
-                          direction,
-                          length,
-                          unsvg::COLORS[color_index],
-                      )
-                      .unwrap();
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  } else {
-                      let (new_x, new_y) = unsvg::get_end_coordinates(x, y, direction, length);
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  }
-              }
-              Command::Back(_numpixels) => {
-                  if is_pen_down {
-                      let color_index: usize =
-                          queries.color[1..].parse::<usize>().expect("Invalid color");
-                      let (new_x, new_y) = Image::draw_simple_line(
-                          image,
-                          x,
-                          y,
-                          direction + 180,
-                          length,
-                          unsvg::COLORS[color_index],
-                      )
-                      .unwrap();
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  } else {
-                      let (new_x, new_y) =
-                          unsvg::get_end_coordinates(x, y, direction + 180, length);
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  }
-              }
-              Command::Right(_numpixels) => {
-                  if is_pen_down {
-                      let color_index: usize =
-                          queries.color[1..].parse::<usize>().expect("Invalid color");
-                      let (new_x, new_y) = Image::draw_simple_line(
-                          image,
-                          x,
-                          y,
-                          direction + 90,
-                          length,
-                          unsvg::COLORS[color_index],
-                      )
-                      .unwrap();
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  } else {
-                      let (new_x, new_y) =
-                          unsvg::get_end_coordinates(x, y, direction + 90, length);
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  }
-              }
-              Command::Left(_numpixels) => {
-                  if is_pen_down {
-                      let color_index: usize =
-                          queries.color[1..].parse::<usize>().expect("Invalid color");
-                      let (new_x, new_y) = Image::draw_simple_line(
-                          image,
-                          x,
-                          y,
-                          direction + 270,
-                          length,
-                          unsvg::COLORS[color_index],
-                      )
-                      .unwrap();
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  } else {
-                      let (new_x, new_y) =
-                          unsvg::get_end_coordinates(x, y, direction + 270, length);
-                      queries.xcor = format!("{}{}", "\"", new_x);
-                      queries.ycor = format!("{}{}", "\"", new_y);
-                  }
-              }
+          let offset = match command {
+              Command::Forward(_numpixels) => 0.0,
+              Command::Back(_numpixels) => 180.0,
+              Command::Right(_numpixels) => 90.0,
+              Command::Left(_numpixels) => 270.0,
               _ => {
                   return Err("Invalid Command".to_string());
               }
-          }
+          };
+          move_turtle(queries, image, direction + offset, length)?;
       }
       Command::Setpencolor(colorcode) => {
           let table = if is_in_procedure {
@@ -145,6 +246,43 @@ pub fn execute_command(
           let result = parse_operation(colorcode, table, queries)?;
           queries.color = result;
       }
+      Command::Setbackground(colorcode) => {
+          let table = if is_in_procedure {
+              procedure_args
+          } else {
+              variable_table
+          };
+          let result = parse_operation(colorcode, table, queries)?;
+          let color = result[1..]
+              .parse::<usize>()
+              .ok()
+              .and_then(|index| unsvg::COLORS.get(index))
+              .ok_or_else(|| format!("SETBACKGROUND: invalid colour {}", result))?;
+          image.set_background(*color);
+      }
+      Command::Setpenalpha(alpha) | Command::Setfillalpha(alpha) => {
+          let table = if is_in_procedure {
+              procedure_args
+          } else {
+              variable_table
+          };
+          let result = parse_operation(alpha, table, queries)?;
+          match result[1..].parse::<f32>() {
+              Ok(value) if (0.0..=1.0).contains(&value) => {}
+              _ => return Err(format!("Opacity must be between 0 and 1, got {}", result)),
+          }
+          match command {
+              Command::Setpenalpha(_alpha) => {
+                  queries.pen_alpha = result;
+              }
+              Command::Setfillalpha(_alpha) => {
+                  queries.fill_alpha = result;
+              }
+              _ => {
+                  return Err("Invalid Command".to_string());
+              }
+          }
+      }
       Command::Turn(degrees) | Command::Setheading(degrees) => {
           let table = if is_in_procedure {
               procedure_args
@@ -154,11 +292,7 @@ pub fn execute_command(
           let result = parse_operation(degrees, table, queries)?;
           match command {
               Command::Turn(_degrees) => {
-                  let new_direction = queries.heading[1..]
-                      .parse::<i32>()
-                      .expect("cannot parse heading")
-                      + result[1..].parse::<i32>().expect("cannot parse result");
-                  queries.heading = format!("{}{}", "\"", new_direction);
+                  turn_turtle(queries, result[1..].parse::<f32>().expect("cannot parse result"));
               }
               Command::Setheading(_degrees) => {
                   queries.heading = result;
@@ -220,15 +354,16 @@ pub fn execute_command(
           };
           let if_condition = parse_operation(operation, table, queries)?;
           if if_condition == *"TRUE" {
-              for command in commands.iter() {
-                  execute_command(
-                      command,
+              for statement in commands.iter() {
+                  execute_statement(
+                      statement,
                       variable_table,
                       dummy_procedures,
                       is_in_procedure,
                       procedure_args,
                       queries,
                       image,
+                      observer,
                   )?;
               }
           }
@@ -243,20 +378,37 @@ pub fn execute_command(
               };
               parse_operation(operation, table, queries)? == *"TRUE"
           } {
-              for command in commands.iter() {
+              observer.loop_iteration()?;
+              for statement in commands.iter() {
                   // Now variable_table is not immutably borrowed in this scope
-                  execute_command(
-                      command,
+                  execute_statement(
+                      statement,
                       variable_table,
                       dummy_procedures,
                       is_in_procedure,
                       procedure_args,
                       queries,
                       image,
+                      observer,
                   )?;
               }
           }
       }
+      Command::Lsystem(lsystem) => {
+          let table = if is_in_procedure {
+              &procedure_args
+          } else {
+              &variable_table
+          };
+          draw_lsystem(lsystem, table, queries, image, observer)?;
+      }
+      Command::Lsystemfile(path) => {
+          let path = queries.source_dir.join(path);
+          let content = std::fs::read_to_string(&path)
+              .map_err(|e| format!("LSYSTEMFILE: cannot read {}: {}", path.display(), e))?;
+          let lsystem = LSystem::from_rule_file(&content)?;
+          draw_lsystem(&lsystem, variable_table, queries, image, observer)?;
+      }
       Command::Procedure(procedure_name, params) => {
           // get procedure name
           let procedure = dummy_procedures.get(procedure_name).expect("msg");
@@ -283,24 +435,191 @@ pub fn execute_command(
               }
           }
 
-          for command in commands {
-              if let Err(e) = execute_command(
-                  &command,
+          observer.enter_procedure(procedure_name, &args_table)?;
+          // observers are told the procedure is over even when its body fails
+          let result = commands.iter().try_for_each(|statement| {
+              execute_statement(
+                  statement,
                   variable_table,
                   dummy_procedures,
                   true,
                   &mut args_table,
                   queries,
                   image,
-              ) {
-                  eprintln!(
-                      "\x1b[31mError processing commands inside a procedure: {}\x1b[0m",
-                      e
-                  );
-                  std::process::exit(1);
-              }
+                  observer,
+              )
+          });
+          observer.exit_procedure(procedure_name);
+          result?;
+      }
+  }
+  Ok(())
+}
+
+/// Rewrite an L-system and move the turtle through the result
+fn draw_lsystem(
+  lsystem: &LSystem,
+  table: &HashMap<String, String>,
+  queries: &mut QueriesStruct,
+  image: &mut dyn RenderBackend,
+  observer: &mut dyn Observer,
+) -> Result<(), String> {
+  let iterations = parse_operation(&lsystem.iterations, table, queries)?;
+  let angle = parse_operation(&lsystem.angle, table, queries)?;
+  let step = parse_operation(&lsystem.step, table, queries)?;
+  let iterations = iterations[1..]
+      .parse::<f32>()
+      .map_err(|_| "LSYSTEM: iterations is not a number".to_string())?;
+  if iterations < 0.0 || iterations.fract() != 0.0 {
+      return Err("LSYSTEM: iterations must be a whole number".to_string());
+  }
+  let angle = angle[1..]
+      .parse::<f32>()
+      .map_err(|_| "LSYSTEM: angle is not a number".to_string())?;
+  let step = step[1..]
+      .parse::<f32>()
+      .map_err(|_| "LSYSTEM: step is not a number".to_string())?;
+
+  for symbol in lsystem.expand(iterations as usize)?.chars() {
+      // limits are checked between statements, which is too late here
+      observer.drawing(image.segments())?;
+      match symbol {
+          'F' | 'G' => {
+              let direction = queries.heading[1..]
+                  .parse::<f32>()
+                  .expect("cannot parse as direction");
+              move_turtle(queries, image, direction, step)?;
           }
+          'f' => {
+              let direction = queries.heading[1..]
+                  .parse::<f32>()
+                  .expect("cannot parse as direction");
+              let is_pen_down = queries.is_pen_down.clone();
+              queries.is_pen_down = "FALSE".to_string();
+              move_turtle(queries, image, direction, step)?;
+              queries.is_pen_down = is_pen_down;
+          }
+          '+' => turn_turtle(queries, -angle),
+          '-' => turn_turtle(queries, angle),
+          '[' => queries.push_turtle(),
+          ']' => queries.pop_turtle()?,
+          _ => {}
       }
   }
   Ok(())
-}
\ No newline at end of file
+}
+
+/// Move the turtle `length` pixels towards `direction` (in degrees),
+/// drawing a line behind it if the pen is down
+pub fn move_turtle(
+  queries: &mut QueriesStruct,
+  image: &mut dyn RenderBackend,
+  direction: f32,
+  length: f32,
+) -> Result<(), String> {
+  let is_pen_down = parse_boolean(&queries.is_pen_down)?;
+  let x = queries.xcor[1..]
+      .parse::<f32>()
+      .expect("cannot parse as x coordinate");
+  let y = queries.ycor[1..]
+      .parse::<f32>()
+      .expect("cannot parse as y coordinate");
+  let (new_x, new_y) = get_end_coordinates(x, y, direction, length);
+  queries.xcor = format!("{}{}", "\"", new_x);
+  queries.ycor = format!("{}{}", "\"", new_y);
+  image.set_turtle(current_pose(queries)?);
+  if is_pen_down {
+      image.set_style(current_style(queries));
+      image.move_to((quantize(x), quantize(y)));
+      image.line_to((new_x, new_y));
+  } else {
+      image.move_to((new_x, new_y));
+  }
+  Ok(())
+}
+
+/// Draw the turtle as a small arrow at its position and heading,
+/// if it has been shown with SHOWTURTLE
+pub fn draw_turtle(queries: &QueriesStruct, image: &mut dyn RenderBackend) -> Result<(), String> {
+  if !parse_boolean(&queries.is_turtle_shown)? {
+      return Ok(());
+  }
+  let x = queries.xcor[1..]
+      .parse::<f32>()
+      .expect("cannot parse as x coordinate");
+  let y = queries.ycor[1..]
+      .parse::<f32>()
+      .expect("cannot parse as y coordinate");
+  let direction = queries.heading[1..]
+      .parse::<f32>()
+      .expect("cannot parse as direction");
+
+  image.set_turtle(current_pose(queries)?);
+  image.set_style(current_style(queries));
+  image.fill(&[
+      get_end_coordinates(x, y, direction, 12.0),
+      get_end_coordinates(x, y, direction + 150.0, 8.0),
+      (quantize(x), quantize(y)),
+      get_end_coordinates(x, y, direction - 150.0, 8.0),
+  ]);
+  Ok(())
+}
+
+/// The pen colour and opacity the turtle is currently drawing with
+pub fn current_style(queries: &QueriesStruct) -> Style {
+  let color_index: usize = queries.color[1..].parse::<usize>().expect("Invalid color");
+  Style {
+      color: unsvg::COLORS[color_index],
+      pen_alpha: queries.pen_alpha[1..].parse::<f32>().expect("Invalid opacity"),
+      fill_alpha: queries.fill_alpha[1..].parse::<f32>().expect("Invalid opacity"),
+      ..Style::default()
+  }
+}
+
+/// Where the turtle is, as told to the backend
+pub fn current_pose(queries: &QueriesStruct) -> Result<TurtlePose, String> {
+  Ok(TurtlePose {
+      position: (
+          queries.xcor[1..]
+              .parse::<f32>()
+              .expect("cannot parse as x coordinate"),
+          queries.ycor[1..]
+              .parse::<f32>()
+              .expect("cannot parse as y coordinate"),
+      ),
+      heading: queries.heading[1..]
+          .parse::<f32>()
+          .expect("cannot parse as direction"),
+      is_pen_down: parse_boolean(&queries.is_pen_down)?,
+      is_shown: parse_boolean(&queries.is_turtle_shown)?,
+  })
+}
+
+/// Turn the turtle clockwise by `degrees`. Headings are kept to a fraction
+/// of a degree so L-system angles such as 22.5 work; before L-systems they
+/// were whole degrees, and a fractional TURN or SETHEADING stopped the program.
+pub fn turn_turtle(queries: &mut QueriesStruct, degrees: f32) {
+  let new_direction = queries.heading[1..]
+      .parse::<f32>()
+      .expect("cannot parse heading")
+      + degrees;
+  queries.heading = format!("{}{}", "\"", new_direction);
+}
+
+/// Same as `unsvg::get_end_coordinates`, but keeps fractional directions
+/// so that L-system angles such as 22.5 degrees do not drift
+pub fn get_end_coordinates(x: f32, y: f32, direction: f32, length: f32) -> (f32, f32) {
+  let x = quantize(x);
+  let y = quantize(y);
+  let direction_rad = (direction - 90.0).to_radians();
+
+  (
+      quantize(x + direction_rad.cos() * length),
+      quantize(y + direction_rad.sin() * length),
+  )
+}
+
+/// Round a coordinate the same way `unsvg` does
+fn quantize(value: f32) -> f32 {
+  (value * 256.0).round() / 256.0
+}
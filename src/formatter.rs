@@ -0,0 +1,411 @@
+use crate::check::arity;
+use crate::commands::Command;
+use crate::parser::parse_command;
+use crate::procedures::DummyProcedure;
+use crate::utils::{extract_operations, operation_length, FmtArgs};
+use std::collections::HashMap;
+
+const INDENT: &str = "  ";
+
+/// Words written in capitals wherever they appear in an expression
+const VALUE_WORDS: [&str; 14] = [
+    "EQ", "NE", "AND", "OR", "GT", "LT", "XCOR", "YCOR", "HEADING", "COLOR", "+", "-", "*", "/",
+];
+
+/// A word of the program and the line it was on
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+/// What a line of the file was, before it is split into commands
+enum Token<'a> {
+    Word(Word<'a>),
+    Comment(&'a str),
+    Blank,
+}
+
+/// The program as it is formatted: commands with their blocks,
+/// along with the comments and blank lines between them
+enum Item {
+    Command(Command),
+    /// A command written back just as it was, such as LSYSTEMFILE,
+    /// which would otherwise be replaced by the file it reads
+    Verbatim(String),
+    Block(Command, Vec<Item>),
+    Procedure(String, Vec<String>, Vec<Item>),
+    Comment(String),
+    Blank,
+}
+
+struct Formatter<'a> {
+    tokens: Vec<Token<'a>>,
+    next: usize,
+    /// Every procedure in the file, with its parameters, so calls can be found
+    procedures: HashMap<String, DummyProcedure>,
+}
+
+/// `word` in capitals if it is one of `keywords`, whatever case it was written in
+fn capitalise(word: &str, keywords: &[&str]) -> String {
+    let upper = word.to_uppercase();
+    if keywords.contains(&upper.as_str()) {
+        upper
+    } else {
+        word.to_string()
+    }
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Formatter<'a> {
+        let mut tokens = Vec::new();
+        let mut procedures = HashMap::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with("//") {
+                tokens.push(Token::Comment(line));
+            } else if line.is_empty() {
+                tokens.push(Token::Blank);
+            } else {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words[0].eq_ignore_ascii_case("TO") && words.len() > 1 {
+                    let params = words[2..].iter().map(|&s| s.to_string()).collect();
+                    procedures.insert(
+                        words[1].to_string(),
+                        DummyProcedure {
+                            args: params,
+                            commands: Vec::new(),
+                        },
+                    );
+                }
+                tokens.extend(words.into_iter().map(|text| {
+                    Token::Word(Word {
+                        text,
+                        line: index + 1,
+                    })
+                }));
+            }
+        }
+        Formatter {
+            tokens,
+            next: 0,
+            procedures,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next)
+    }
+
+    /// The next word, if the next token is a word
+    fn peek_word(&self) -> Option<Word<'a>> {
+        match self.peek() {
+            Some(Token::Word(word)) => Some(*word),
+            _ => None,
+        }
+    }
+
+    /// The words of a command, which may not be broken by a comment or blank line
+    fn take_words(&mut self, count: usize, keyword: &Word) -> Result<Vec<Word<'a>>, String> {
+        let mut words = Vec::new();
+        for _ in 0..count {
+            match self.peek_word() {
+                Some(word) => {
+                    words.push(word);
+                    self.next += 1;
+                }
+                None => {
+                    return Err(format!(
+                        "line {}: {} is missing a value",
+                        keyword.line, keyword.text
+                    ))
+                }
+            }
+        }
+        Ok(words)
+    }
+
+    /// The words left on the same line as `keyword`
+    fn rest_of_line(&mut self, keyword: &Word) -> Vec<Word<'a>> {
+        let mut words = Vec::new();
+        while let Some(word) = self.peek_word().filter(|word| word.line == keyword.line) {
+            words.push(word);
+            self.next += 1;
+        }
+        words
+    }
+
+    /// Take one expression from the line of `keyword`, writing its operators and queries in capitals
+    fn take_operation(&mut self, keyword: &Word) -> Result<Vec<String>, String> {
+        let mut rest: Vec<String> = Vec::new();
+        let mut index = self.next;
+        while let Some(Token::Word(word)) = self.tokens.get(index) {
+            if word.line != keyword.line {
+                break;
+            }
+            rest.push(capitalise(word.text, &VALUE_WORDS));
+            index += 1;
+        }
+        let words: Vec<&str> = rest.iter().map(|word| word.as_str()).collect();
+        let length = operation_length(&words)
+            .ok_or_else(|| format!("line {}: {} is missing a value", keyword.line, keyword.text))?;
+        self.next += length;
+        Ok(rest[..length].to_vec())
+    }
+
+    /// Read items until the word that closes the block, or the end of the file
+    fn items(&mut self, closer: Option<&str>) -> Result<Vec<Item>, String> {
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            let word = match token {
+                Token::Comment(text) => {
+                    items.push(Item::Comment(text.to_string()));
+                    self.next += 1;
+                    continue;
+                }
+                Token::Blank => {
+                    items.push(Item::Blank);
+                    self.next += 1;
+                    continue;
+                }
+                Token::Word(word) => *word,
+            };
+            self.next += 1;
+            let keyword = match self.procedures.contains_key(word.text) {
+                true => word.text.to_string(),
+                false => word.text.to_uppercase(),
+            };
+            match keyword.as_str() {
+                "]" | "END" => {
+                    if closer == Some(keyword.as_str()) {
+                        return Ok(items);
+                    }
+                    return Err(format!("line {}: unexpected {}", word.line, keyword));
+                }
+                "IF" | "WHILE" => {
+                    let condition = self.take_operation(&word)?;
+                    match self.peek_word() {
+                        Some(open) if open.text == "[" => self.next += 1,
+                        _ => {
+                            return Err(format!(
+                                "line {}: {} needs a [ after its condition",
+                                word.line, keyword
+                            ))
+                        }
+                    }
+                    let words: Vec<&str> = condition.iter().map(|word| word.as_str()).collect();
+                    let condition = extract_operations(&words)
+                        .map_err(|e| format!("line {}: {}", word.line, e))?;
+                    let block = self.items(Some("]"))?;
+                    let command = match keyword.as_str() {
+                        "IF" => Command::If(condition, Vec::new()),
+                        _ => Command::Whlie(condition, Vec::new()),
+                    };
+                    items.push(Item::Block(command, block));
+                }
+                "TO" => {
+                    let name = self.take_words(1, &word)?[0];
+                    let mut params = Vec::new();
+                    while let Some(param) =
+                        self.peek_word().filter(|param| param.text.starts_with('"'))
+                    {
+                        params.push(param.text.to_string());
+                        self.next += 1;
+                    }
+                    let body = self.items(Some("END"))?;
+                    items.push(Item::Procedure(name.text.to_string(), params, body));
+                }
+                "LSYSTEMFILE" => {
+                    let mut words = vec![keyword.clone()];
+                    words.extend(
+                        self.rest_of_line(&word)
+                            .iter()
+                            .map(|word| word.text.to_string()),
+                    );
+                    items.push(Item::Verbatim(words.join(" ")));
+                }
+                _ => {
+                    let mut words = vec![keyword.clone()];
+                    match (keyword.as_str(), arity(&keyword)) {
+                        ("LSYSTEM", _) => words.extend(
+                            self.rest_of_line(&word)
+                                .iter()
+                                .map(|word| word.text.to_string()),
+                        ),
+                        ("MAKE", _) | ("ADDASSIGN", _) => {
+                            words.push(self.take_words(1, &word)?[0].text.to_string());
+                            words.extend(self.take_operation(&word)?);
+                        }
+                        (_, Some(count)) => {
+                            for _ in 0..count {
+                                words.extend(self.take_operation(&word)?);
+                            }
+                        }
+                        (_, None) => match self.procedures.get(&keyword) {
+                            Some(procedure) => {
+                                let count = procedure.args.len();
+                                let args = self.take_words(count, &word)?;
+                                words.extend(args.iter().map(|arg| arg.text.to_string()));
+                            }
+                            None => {
+                                return Err(format!(
+                                    "line {}: unknown command {}",
+                                    word.line, word.text
+                                ))
+                            }
+                        },
+                    }
+                    let text = words.join(" ");
+                    let command = parse_command(&text, &self.procedures)
+                        .map_err(|e| format!("line {}: {}", word.line, e))?;
+                    items.push(Item::Command(command));
+                }
+            }
+        }
+        match closer {
+            Some(closer) => Err(format!("missing {} at the end of the file", closer)),
+            None => Ok(items),
+        }
+    }
+}
+
+/// Write items at `depth` levels of indentation
+fn print_items(items: &[Item], depth: usize, lines: &mut Vec<String>) {
+    let indent = INDENT.repeat(depth);
+    for item in items.iter() {
+        match item {
+            Item::Command(command) => lines.push(format!("{}{}", indent, command.source())),
+            Item::Verbatim(text) | Item::Comment(text) => lines.push(format!("{}{}", indent, text)),
+            Item::Block(command, block) => {
+                lines.push(format!("{}{}", indent, command.source()));
+                print_items(block, depth + 1, lines);
+                lines.push(format!("{}]", indent));
+            }
+            Item::Procedure(name, params, body) => {
+                let mut line = format!("{}TO {}", indent, name);
+                for param in params.iter() {
+                    line.push_str(&format!(" {}", param));
+                }
+                lines.push(line);
+                print_items(body, depth + 1, lines);
+                lines.push(format!("{}END", indent));
+            }
+            Item::Blank => lines.push(String::new()),
+        }
+    }
+}
+
+/// Keep at most one blank line in a row, and none at the start
+/// or end of the file or just inside a block
+fn tidy(lines: Vec<String>) -> Vec<String> {
+    let mut tidied: Vec<String> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            let after_opener = tidied.last().is_none_or(|last| {
+                last.is_empty() || last.ends_with('[') || last.trim_start().starts_with("TO ")
+            });
+            let before_closer = lines[index + 1..]
+                .iter()
+                .find(|next| !next.is_empty())
+                .is_none_or(|next| matches!(next.trim_start(), "]" | "END"));
+            if after_opener || before_closer {
+                continue;
+            }
+        }
+        tidied.push(line.clone());
+    }
+    tidied
+}
+
+/// The program in its standard layout: one command per line, keywords in
+/// capitals and blocks indented by two spaces, keeping comments and single blank lines
+pub fn format_source(source: &str) -> Result<String, String> {
+    let mut formatter = Formatter::new(source);
+    let items = formatter.items(None)?;
+    let mut lines = Vec::new();
+    print_items(&items, 0, &mut lines);
+    let mut formatted = tidy(lines).join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+/// Format files in place, or with `--check` only say which are not formatted
+pub fn run_fmt(args: FmtArgs) -> Result<(), String> {
+    let mut unformatted = 0;
+    for path in args.files.iter() {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let formatted = format_source(&source)
+            .map_err(|e| format!("cannot format {}: {}", path.display(), e))?;
+        if formatted == source {
+            continue;
+        }
+        if args.check {
+            let line = source
+                .lines()
+                .zip(formatted.lines())
+                .position(|(old, new)| old != new)
+                .unwrap_or_else(|| source.lines().count().min(formatted.lines().count()));
+            println!(
+                "{} is not formatted, from line {}",
+                path.display(),
+                line + 1
+            );
+            unformatted += 1;
+        } else {
+            std::fs::write(path, formatted).map_err(|e| format!("{}: {}", path.display(), e))?;
+            println!("formatted {}", path.display());
+        }
+    }
+    match unformatted {
+        0 => Ok(()),
+        1 => Err("1 file is not formatted".to_string()),
+        _ => Err(format!("{} files are not formatted", unformatted)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_are_indented_by_two_spaces() {
+        let source = "PENDOWN\nMAKE \"X \"1\nWHILE LT :X \"5 [\nFORWARD :X\n      IF EQ :X \"3 [\n TURN \"90\n]\nADDASSIGN \"X \"1\n]\n";
+        let expected = "PENDOWN\nMAKE \"X \"1\nWHILE LT :X \"5 [\n  FORWARD :X\n  IF EQ :X \"3 [\n    TURN \"90\n  ]\n  ADDASSIGN \"X \"1\n]\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn procedure_bodies_are_indented() {
+        let source =
+            "TO SIDE \"LENGTH\n      FORWARD :LENGTH\n   TURN \"90\nEND\nMAKE \"S \"4\nSIDE :S\n";
+        let expected =
+            "TO SIDE \"LENGTH\n  FORWARD :LENGTH\n  TURN \"90\nEND\nMAKE \"S \"4\nSIDE :S\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn keywords_are_written_in_capitals() {
+        let source =
+            "to side \"length\nforward :length\nend\npendown\nif  gt  xcor \"1 [ side :length ]\n";
+        let expected = "TO side \"length\n  FORWARD :length\nEND\nPENDOWN\nIF GT XCOR \"1 [\n  side :length\n]\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn comments_and_single_blank_lines_are_kept() {
+        let source = "// draw a line\n\n\n\nPENDOWN\n   // then go up\nFORWARD \"10\n\n";
+        let expected = "// draw a line\n\nPENDOWN\n// then go up\nFORWARD \"10\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn formatting_twice_changes_nothing() {
+        let source =
+            "to side \"length\nforward :length\nend\n// a comment\nif eq \"1 \"1 [\nside :x\n]\n";
+        let once = format_source(source).unwrap();
+        assert_eq!(format_source(&once).unwrap(), once);
+    }
+}
@@ -1,6 +1,27 @@
+pub mod animation;
+pub mod backend;
+pub mod canvas;
 pub mod commands;
+pub mod display_list;
+pub mod document;
+pub mod dxf;
 pub mod operations;
 pub mod procedures;
 pub mod parser;
 pub mod executer;
 pub mod utils;
+pub mod lsystem;
+pub mod plotter;
+pub mod terminal;
+pub mod svg;
+pub mod repl;
+pub mod session;
+pub mod debugger;
+pub mod trace;
+pub mod limits;
+pub mod check;
+pub mod types;
+pub mod formatter;
+pub mod ast;
+pub mod lsp;
+pub mod lint;
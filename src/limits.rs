@@ -0,0 +1,147 @@
+use crate::commands::Statement;
+use crate::executer::{Observer, Scope};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Bounds on how much work a program may do. `None` means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Statements run, counting every time round a WHILE loop
+    pub max_steps: Option<u64>,
+    /// Procedure calls inside each other
+    pub max_depth: Option<usize>,
+    /// Time the program may run for
+    pub timeout: Option<Duration>,
+    /// Lines and arcs drawn
+    pub max_segments: Option<usize>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.max_steps.is_none()
+            && self.max_depth.is_none()
+            && self.timeout.is_none()
+            && self.max_segments.is_none()
+    }
+}
+
+/// Parse a time such as `2.5`, `2.5s` (seconds) or `500ms`
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, scale) = match value.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => (value.strip_suffix('s').unwrap_or(value), 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => {
+            Ok(Duration::from_secs_f64(number * scale))
+        }
+        _ => Err(format!("invalid time {}", value)),
+    }
+}
+
+/// Stops the program with an error once it goes past one of its limits
+pub struct LimitObserver {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    started: Instant,
+    /// Time the program was held up by other observers, such as the
+    /// debugger, which doesn't count towards the timeout
+    paused: Duration,
+    /// The statement being run, to say where the program was stopped
+    line: usize,
+}
+
+impl LimitObserver {
+    pub fn new(limits: Limits) -> LimitObserver {
+        LimitObserver {
+            limits,
+            steps: 0,
+            depth: 0,
+            started: Instant::now(),
+            paused: Duration::ZERO,
+            line: 0,
+        }
+    }
+
+    /// Count a step and check the step and time limits
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps > max_steps {
+                return Err(format!(
+                    "line {}: stopped after {} steps (--max-steps)",
+                    self.line, max_steps
+                ));
+            }
+        }
+        self.check_time()
+    }
+
+    fn check_time(&self) -> Result<(), String> {
+        if let Some(timeout) = self.limits.timeout {
+            if self.started.elapsed().saturating_sub(self.paused) > timeout {
+                return Err(format!(
+                    "line {}: stopped after {} seconds (--timeout)",
+                    self.line,
+                    timeout.as_secs_f32()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_segments(&self, segments: usize) -> Result<(), String> {
+        match self.limits.max_segments {
+            Some(max_segments) if segments > max_segments => Err(format!(
+                "line {}: stopped after drawing {} lines and arcs (--max-segments)",
+                self.line, max_segments
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Observer for LimitObserver {
+    fn before(&mut self, statement: &Statement, _scope: &Scope) -> Result<(), String> {
+        self.line = statement.line;
+        self.step()
+    }
+
+    fn after(&mut self, statement: &Statement, scope: &Scope) -> Result<(), String> {
+        self.line = statement.line;
+        self.check_segments(scope.segments)
+    }
+
+    fn enter_procedure(
+        &mut self,
+        name: &str,
+        _args: &HashMap<String, String>,
+    ) -> Result<(), String> {
+        self.depth += 1;
+        match self.limits.max_depth {
+            Some(max_depth) if self.depth > max_depth => Err(format!(
+                "line {}: stopped calling {}, as it would nest more than {} procedure calls (--max-depth)",
+                self.line, name, max_depth
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn exit_procedure(&mut self, _name: &str) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    fn loop_iteration(&mut self) -> Result<(), String> {
+        self.step()
+    }
+
+    fn drawing(&mut self, segments: usize) -> Result<(), String> {
+        self.check_segments(segments)?;
+        self.check_time()
+    }
+
+    fn paused(&mut self, duration: Duration) {
+        self.paused += duration;
+    }
+}
@@ -0,0 +1,526 @@
+use crate::check::{plural, render, tokens, variables, Diagnostic, Severity};
+use crate::commands::{Command, Statement};
+use crate::operations::Operation;
+use crate::procedures::DummyProcedure;
+use crate::utils::{extract_commands, LintArgs};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The file a config is read from when `--config` is not given, looked
+/// for next to the file being linted and then in each directory above it
+pub const CONFIG_FILE: &str = "rslogo-lint.toml";
+
+/// Words that can be used as values without a " or :
+const QUERIES: [&str; 4] = ["XCOR", "YCOR", "HEADING", "COLOR"];
+
+/// Every rule, by the ID it is turned on and off with, and what it looks for
+pub const RULES: [(&str, &str); 5] = [
+    (
+        "variable-case",
+        "variable names that differ only by case, which are different variables",
+    ),
+    ("pen-already-down", "PENDOWN when the pen is already down"),
+    (
+        "infinite-while",
+        "WHILE loops whose block never changes a variable in their condition",
+    ),
+    (
+        "shadowed-parameter",
+        "procedure parameters that hide a variable or an earlier parameter",
+    ),
+    (
+        "magic-number",
+        "numbers other than 0 and 1 written out many times instead of being made once",
+    ),
+];
+
+fn default_magic_number_count() -> usize {
+    4
+}
+
+/// Which rules are on, read from a file like:
+///
+/// ```toml
+/// magic-number-count = 6
+///
+/// [rules]
+/// pen-already-down = false
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Rules turned on or off by ID. Rules not given are on.
+    #[serde(default)]
+    pub rules: HashMap<String, bool>,
+
+    /// How many times a number has to be written for magic-number to warn about it
+    #[serde(default = "default_magic_number_count")]
+    pub magic_number_count: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rules: HashMap::new(),
+            magic_number_count: default_magic_number_count(),
+        }
+    }
+}
+
+impl Config {
+    /// Read a config, making sure every rule it names exists
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Config =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for id in config.rules.keys() {
+            if !RULES.iter().any(|(rule, _)| rule == id) {
+                let ids: Vec<&str> = RULES.iter().map(|(rule, _)| *rule).collect();
+                return Err(format!(
+                    "{}: there is no rule {}, the rules are {}",
+                    path.display(),
+                    id,
+                    ids.join(", ")
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The config given with `--config`, or else the nearest config file to `file`
+    fn find(config: &Option<PathBuf>, file: &Path) -> Result<Config, String> {
+        if let Some(path) = config {
+            return Config::load(path);
+        }
+        for directory in file.ancestors().skip(1) {
+            let path = directory.join(CONFIG_FILE);
+            if path.is_file() {
+                return Config::load(&path);
+            }
+        }
+        Ok(Config::default())
+    }
+
+    pub fn enabled(&self, rule: &str) -> bool {
+        self.rules.get(rule).copied().unwrap_or(true)
+    }
+}
+
+/// Every word an operation is built from, operators left out
+fn words(operation: &Operation) -> Vec<&str> {
+    match (operation.operator(), operation) {
+        (Some((_, a, b)), _) => {
+            let mut found = words(a);
+            found.extend(words(b));
+            found
+        }
+        (None, Operation::Base(word)) => vec![word.as_str()],
+        (None, _) => Vec::new(),
+    }
+}
+
+/// Call `visit` on each statement, and on the statements in its block after it
+fn walk<'s>(statements: &'s [Statement], visit: &mut impl FnMut(&'s Statement)) {
+    for statement in statements.iter() {
+        visit(statement);
+        if let Command::If(_, block) | Command::Whlie(_, block) = &statement.command {
+            walk(block, visit);
+        }
+    }
+}
+
+/// The pen state both ways could leave it in, if they agree
+fn join(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match a == b {
+        true => a,
+        false => None,
+    }
+}
+
+struct Linter<'a> {
+    source: Vec<&'a str>,
+    statements: &'a [Statement],
+    procedures: &'a HashMap<String, DummyProcedure>,
+    config: &'a Config,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    /// The column and length of `word` on a line, or of the whole line if it is not there.
+    /// A variable is found whether it is written with " or :.
+    fn span(&self, line: usize, word: &str) -> (usize, usize) {
+        let text = self.source.get(line - 1).copied().unwrap_or_default();
+        let found = tokens(text).into_iter().find(|(_, token)| {
+            *token == word
+                || (word.starts_with(':') && token.starts_with('"') && token[1..] == word[1..])
+        });
+        match found {
+            Some((column, token)) => (column, token.chars().count()),
+            None => {
+                let indent = text.len() - text.trim_start().len();
+                (indent + 1, text.trim().chars().count())
+            }
+        }
+    }
+
+    fn warn(&mut self, rule: &str, line: usize, word: &str, message: String) {
+        let span = self.span(line, word);
+        self.warn_at(rule, line, span, message);
+    }
+
+    fn warn_at(&mut self, rule: &str, line: usize, span: (usize, usize), message: String) {
+        if !self.config.enabled(rule) {
+            return;
+        }
+        self.diagnostics.push(Diagnostic {
+            line,
+            column: span.0,
+            length: span.1,
+            severity: Severity::Warning,
+            message: format!("{} [{}]", message, rule),
+        });
+    }
+
+    /// The line each procedure's TO is on. A procedure defined twice
+    /// has the body of the second, so that is the one kept.
+    fn definitions(&self) -> BTreeMap<usize, &'a str> {
+        let mut lines = HashMap::new();
+        for (index, text) in self.source.iter().enumerate() {
+            let words = tokens(text);
+            if let [(_, "TO"), (_, name), ..] = words.as_slice() {
+                if let Some((name, _)) = self.procedures.get_key_value(*name) {
+                    lines.insert(name.as_str(), index + 1);
+                }
+            }
+        }
+        lines.into_iter().map(|(name, line)| (line, name)).collect()
+    }
+
+    /// Each procedure's body, with the line of its TO, in the order they are defined
+    fn bodies(&self) -> Vec<(usize, &'a str, &'a DummyProcedure)> {
+        self.definitions()
+            .into_iter()
+            .map(|(line, name)| (line, name, &self.procedures[name]))
+            .collect()
+    }
+
+    fn variable_case(&mut self) {
+        let mut uses: Vec<(usize, String)> = Vec::new();
+        for (line, _, procedure) in self.bodies() {
+            uses.extend(
+                procedure
+                    .args
+                    .iter()
+                    .map(|arg| (line, arg.replace('"', ":"))),
+            );
+        }
+        let mut bodies: Vec<&[Statement]> = vec![self.statements];
+        bodies.extend(
+            self.procedures
+                .values()
+                .map(|procedure| &procedure.commands[..]),
+        );
+        for statements in bodies {
+            walk(statements, &mut |statement| {
+                let line = statement.line;
+                match &statement.command {
+                    Command::Make(name, _) | Command::Addassign(name, _) => {
+                        uses.push((line, name.replace('"', ":")))
+                    }
+                    Command::Procedure(_, items) => uses.extend(
+                        items
+                            .iter()
+                            .filter(|item| item.starts_with(':'))
+                            .map(|item| (line, item.clone())),
+                    ),
+                    _ => {}
+                }
+                for operation in statement.command.operations() {
+                    uses.extend(
+                        variables(operation)
+                            .iter()
+                            .map(|name| (line, name.to_string())),
+                    );
+                }
+            });
+        }
+        uses.sort();
+
+        // the first way each name is written, and where
+        let mut first: HashMap<String, (usize, String)> = HashMap::new();
+        let mut reported = HashSet::new();
+        for (line, name) in uses {
+            let (first_line, spelling) = first
+                .entry(name.to_lowercase())
+                .or_insert_with(|| (line, name.clone()))
+                .clone();
+            if spelling != name && reported.insert(name.clone()) {
+                let message = format!(
+                    "{} is written {} on line {}, but differs in case so is a different variable",
+                    name, spelling, first_line
+                );
+                self.warn("variable-case", line, &name, message);
+            }
+        }
+    }
+
+    /// Follow whether the pen is down through statements, warning about PENDOWN
+    /// when it already is if `report` is set. `None` is a pen that could be either.
+    fn pen(
+        &mut self,
+        statements: &[Statement],
+        mut pen: Option<bool>,
+        report: bool,
+    ) -> Option<bool> {
+        for statement in statements.iter() {
+            match &statement.command {
+                Command::Pendown => {
+                    if report && pen == Some(true) {
+                        let message = "PENDOWN does nothing, the pen is already down".to_string();
+                        self.warn("pen-already-down", statement.line, "PENDOWN", message);
+                    }
+                    pen = Some(true);
+                }
+                Command::Penup => pen = Some(false),
+                Command::Popturtle => pen = None,
+                Command::If(_, block) => {
+                    let end = self.pen(block, pen, report);
+                    pen = join(pen, end);
+                }
+                Command::Whlie(_, block) => {
+                    // the block may start with the pen as it ends the block, so go round once first
+                    let end = self.pen(block, pen, false);
+                    let start = join(pen, end);
+                    let end = self.pen(block, start, report);
+                    pen = join(start, end);
+                }
+                Command::Procedure(name, _) => {
+                    if let Some(procedure) = self.procedures.get(name) {
+                        pen = self.pen(&procedure.commands, pen, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+        pen
+    }
+
+    fn pen_already_down(&mut self) {
+        // a procedure can be called with the pen either way
+        for (_, _, procedure) in self.bodies() {
+            self.pen(&procedure.commands, None, true);
+        }
+        // the pen starts up
+        self.pen(self.statements, Some(false), true);
+    }
+
+    /// The global variables a procedure changes with ADDASSIGN, including in the procedures it calls
+    fn global_changes(&self, name: &str, changed: &mut HashSet<String>) {
+        if let Some(procedure) = self.procedures.get(name) {
+            walk(
+                &procedure.commands,
+                &mut |statement| match &statement.command {
+                    Command::Addassign(name, _) => {
+                        changed.insert(name.replace('"', ":"));
+                    }
+                    Command::Procedure(name, _) => self.global_changes(name, changed),
+                    _ => {}
+                },
+            );
+        }
+    }
+
+    /// The variables a block changes. Inside a procedure ADDASSIGN only changes
+    /// global variables, which the procedure cannot see.
+    fn changes(&self, block: &[Statement], in_procedure: bool) -> HashSet<String> {
+        let mut changed = HashSet::new();
+        walk(block, &mut |statement| match &statement.command {
+            Command::Make(name, _) => {
+                changed.insert(name.replace('"', ":"));
+            }
+            Command::Addassign(name, _) if !in_procedure => {
+                changed.insert(name.replace('"', ":"));
+            }
+            Command::Procedure(name, _) if !in_procedure => self.global_changes(name, &mut changed),
+            _ => {}
+        });
+        changed
+    }
+
+    fn infinite_while(&mut self) {
+        let mut bodies: Vec<(&[Statement], bool)> = vec![(self.statements, false)];
+        bodies.extend(
+            self.procedures
+                .values()
+                .map(|procedure| (&procedure.commands[..], true)),
+        );
+        let mut loops = Vec::new();
+        for (statements, in_procedure) in bodies {
+            walk(statements, &mut |statement| {
+                if let Command::Whlie(condition, block) = &statement.command {
+                    loops.push((statement.line, condition, block, in_procedure));
+                }
+            });
+        }
+        for (line, condition, block, in_procedure) in loops {
+            let read = variables(condition);
+            // a loop on the turtle can end by moving it, and one on
+            // values alone is reported by rslogo check
+            let moves = words(condition).iter().any(|word| QUERIES.contains(word));
+            if read.is_empty() || moves {
+                continue;
+            }
+            let changed = self.changes(block, in_procedure);
+            if !read.iter().any(|name| changed.contains(*name)) {
+                let mut names: Vec<&str> = read.clone();
+                names.sort();
+                names.dedup();
+                let message = format!(
+                    "WHILE may never stop, nothing in its block changes {}",
+                    names.join(" or ")
+                );
+                self.warn("infinite-while", line, "WHILE", message);
+            }
+        }
+    }
+
+    fn shadowed_parameter(&mut self) {
+        let mut globals: HashMap<String, usize> = HashMap::new();
+        walk(self.statements, &mut |statement| {
+            if let Command::Make(name, _) = &statement.command {
+                globals
+                    .entry(name.replace('"', ":"))
+                    .or_insert(statement.line);
+            }
+        });
+        for (line, name, procedure) in self.bodies() {
+            let mut seen = HashSet::new();
+            let words = tokens(self.source[line - 1]);
+            for (index, arg) in procedure.args.iter().enumerate() {
+                let param = arg.replace('"', ":");
+                if !seen.insert(param.clone()) {
+                    let message = format!(
+                        "{} is given twice, so the first {} can never be used",
+                        param, param
+                    );
+                    // after TO and the name
+                    let span = match words.get(index + 2) {
+                        Some((column, word)) => (*column, word.chars().count()),
+                        None => self.span(line, arg),
+                    };
+                    self.warn_at("shadowed-parameter", line, span, message);
+                } else if let Some(made) = globals.get(&param) {
+                    let message = format!(
+                        "{} hides the variable made on line {}, which {} cannot see",
+                        param, made, name
+                    );
+                    self.warn("shadowed-parameter", line, arg, message);
+                }
+            }
+        }
+    }
+
+    fn magic_number(&mut self) {
+        let mut bodies: Vec<&[Statement]> = vec![self.statements];
+        bodies.extend(
+            self.procedures
+                .values()
+                .map(|procedure| &procedure.commands[..]),
+        );
+        // where each number is first written, and how many times it is
+        let mut numbers: HashMap<String, (usize, usize)> = HashMap::new();
+        for statements in bodies {
+            walk(statements, &mut |statement| {
+                for operation in statement.command.operations() {
+                    for word in words(operation) {
+                        let number = match word.strip_prefix('"').map(str::parse::<f32>) {
+                            Some(Ok(number)) => number,
+                            _ => continue,
+                        };
+                        if number == 0.0 || number == 1.0 {
+                            continue;
+                        }
+                        let entry = numbers
+                            .entry(word.to_string())
+                            .or_insert((statement.line, 0));
+                        entry.0 = entry.0.min(statement.line);
+                        entry.1 += 1;
+                    }
+                }
+            });
+        }
+        let mut numbers: Vec<(String, (usize, usize))> = numbers.into_iter().collect();
+        numbers.sort_by_key(|(_, (line, _))| *line);
+        for (number, (line, count)) in numbers {
+            if count >= self.config.magic_number_count {
+                let message = format!(
+                    "{} is written {} times, MAKE a variable for it so they can be changed together",
+                    number, count
+                );
+                self.warn("magic-number", line, &number, message);
+            }
+        }
+    }
+
+    fn lint(mut self) -> Vec<Diagnostic> {
+        self.variable_case();
+        self.pen_already_down();
+        self.infinite_while();
+        self.shadowed_parameter();
+        self.magic_number();
+        self.diagnostics
+            .sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        self.diagnostics
+    }
+}
+
+/// Warnings about a program that runs but probably does not do what was meant.
+/// The program has to parse, so problems found by `check` are not repeated.
+pub fn lint(source: &str, config: &Config) -> Result<Vec<Diagnostic>, String> {
+    let lines: Vec<&str> = source.lines().map(|line| line.trim()).collect();
+    let mut procedures: HashMap<String, DummyProcedure> = HashMap::new();
+    let (statements, _) = extract_commands(&lines, 0, &mut procedures)?;
+    let linter = Linter {
+        source: source.lines().collect(),
+        statements: &statements,
+        procedures: &procedures,
+        config,
+        diagnostics: Vec::new(),
+    };
+    Ok(linter.lint())
+}
+
+/// Lint a file, or with `--list-rules` say what each rule looks for
+pub fn run_lint(args: LintArgs) -> Result<(), String> {
+    let path = match args.file_path {
+        Some(path) if !args.list_rules => path,
+        _ => {
+            for (id, description) in RULES.iter() {
+                println!("{:<20}{}", id, description);
+            }
+            return Ok(());
+        }
+    };
+    let config = Config::find(&args.config, &path)?;
+    let source = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let name = path.display().to_string();
+    let diagnostics = lint(&source, &config).map_err(|e| {
+        format!(
+            "cannot lint {}: {}\nrslogo check {} shows every problem that stops it running",
+            name, e, name
+        )
+    })?;
+    let color = std::env::var_os("NO_COLOR").is_none();
+    for diagnostic in diagnostics.iter() {
+        println!("{}", render(&name, &source, diagnostic, color));
+    }
+    let warnings = plural(diagnostics.len(), "warning");
+    match diagnostics.len() {
+        0 => println!("{}: no problems found", name),
+        _ if args.deny_warnings => return Err(format!("{}: {}", name, warnings)),
+        _ => println!("{}: {}", name, warnings),
+    }
+    Ok(())
+}
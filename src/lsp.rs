@@ -0,0 +1,818 @@
+use crate::check::{check, tokens, Checker, Severity};
+use crate::types::Type;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position, Range,
+    ServerCapabilities, SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::collections::HashMap;
+
+/// Every built in word: its usage and what it does
+const BUILTINS: [(&str, &str, &str); 38] = [
+    ("PENUP", "PENUP", "Stop drawing when the turtle moves."),
+    (
+        "PENDOWN",
+        "PENDOWN",
+        "Draw a line wherever the turtle moves.",
+    ),
+    (
+        "SHOWTURTLE",
+        "SHOWTURTLE",
+        "Draw the turtle on the finished image.",
+    ),
+    (
+        "HIDETURTLE",
+        "HIDETURTLE",
+        "Leave the turtle off the finished image.",
+    ),
+    (
+        "PUSHTURTLE",
+        "PUSHTURTLE",
+        "Save the turtle's position, heading and pen on a stack.",
+    ),
+    (
+        "POPTURTLE",
+        "POPTURTLE",
+        "Go back to the turtle last saved with PUSHTURTLE.",
+    ),
+    (
+        "CLEARSCREEN",
+        "CLEARSCREEN",
+        "Rub out the drawing and put the turtle back in the middle, facing up.",
+    ),
+    (
+        "CLEAN",
+        "CLEAN",
+        "Rub out the drawing, leaving the turtle where it is.",
+    ),
+    (
+        "FORWARD",
+        "FORWARD distance",
+        "Move forward `distance` pixels.",
+    ),
+    ("BACK", "BACK distance", "Move backwards `distance` pixels."),
+    (
+        "LEFT",
+        "LEFT distance",
+        "Move `distance` pixels to the turtle's left, without turning.",
+    ),
+    (
+        "RIGHT",
+        "RIGHT distance",
+        "Move `distance` pixels to the turtle's right, without turning.",
+    ),
+    (
+        "SETPENCOLOR",
+        "SETPENCOLOR colour",
+        "Draw with one of the 16 Logo colours, 0 to 15.",
+    ),
+    (
+        "SETBACKGROUND",
+        "SETBACKGROUND colour",
+        "Fill the background with one of the 16 Logo colours.",
+    ),
+    (
+        "SETPENALPHA",
+        "SETPENALPHA opacity",
+        "Draw lines with an opacity from 0 to 1.",
+    ),
+    (
+        "SETFILLALPHA",
+        "SETFILLALPHA opacity",
+        "Fill the turtle shown by SHOWTURTLE with an opacity from 0 to 1, the only filled shape.",
+    ),
+    ("TURN", "TURN degrees", "Turn clockwise by `degrees`."),
+    (
+        "SETHEADING",
+        "SETHEADING degrees",
+        "Face `degrees` clockwise from straight up.",
+    ),
+    (
+        "SETX",
+        "SETX x",
+        "Move to `x` pixels from the left edge, without drawing.",
+    ),
+    (
+        "SETY",
+        "SETY y",
+        "Move to `y` pixels from the top edge, without drawing.",
+    ),
+    (
+        "MAKE",
+        "MAKE \"name value",
+        "Give the variable `:name` a value.",
+    ),
+    (
+        "ADDASSIGN",
+        "ADDASSIGN \"name number",
+        "Add a number to the variable `:name`.",
+    ),
+    (
+        "IF",
+        "IF condition [",
+        "Run the block up to `]` if the condition is TRUE.",
+    ),
+    (
+        "WHILE",
+        "WHILE condition [",
+        "Run the block up to `]` for as long as the condition is TRUE.",
+    ),
+    (
+        "TO",
+        "TO name \"param...",
+        "Define a procedure, up to END. Parameters are used as `:param`.",
+    ),
+    ("END", "END", "End the procedure started by TO."),
+    (
+        "LSYSTEM",
+        "LSYSTEM \"axiom iterations angle step \"X=rule...",
+        "Draw an L-system.",
+    ),
+    (
+        "LSYSTEMFILE",
+        "LSYSTEMFILE \"path",
+        "Draw the L-system described in a rule file.",
+    ),
+    ("XCOR", "XCOR", "The turtle's x coordinate."),
+    ("YCOR", "YCOR", "The turtle's y coordinate."),
+    (
+        "HEADING",
+        "HEADING",
+        "The direction the turtle faces, in degrees.",
+    ),
+    ("COLOR", "COLOR", "The pen colour."),
+    ("EQ", "EQ a b", "TRUE if `a` and `b` are equal."),
+    ("NE", "NE a b", "TRUE if `a` and `b` are not equal."),
+    ("GT", "GT a b", "TRUE if `a` is greater than `b`."),
+    ("LT", "LT a b", "TRUE if `a` is less than `b`."),
+    ("AND", "AND a b", "TRUE if `a` and `b` are both TRUE."),
+    ("OR", "OR a b", "TRUE if either `a` or `b` is TRUE."),
+];
+
+/// Something named in a file: a procedure, one of its parameters or a variable
+#[derive(Debug, Clone)]
+struct Definition {
+    /// `:name` for parameters and variables
+    name: String,
+    kind: SymbolKind,
+    /// 1-based line, and the column and length of the name on it
+    line: usize,
+    column: usize,
+    length: usize,
+    /// The procedure it belongs to, if it is not in the main program
+    procedure: Option<String>,
+}
+
+/// The procedures and variables of a file, found line by line so that
+/// files that do not parse while they are being typed still have them
+#[derive(Debug, Default)]
+struct Outline {
+    definitions: Vec<Definition>,
+    /// Each procedure's TO and END lines
+    procedures: HashMap<String, (usize, usize)>,
+    /// Each procedure's TO line as written
+    signatures: HashMap<String, String>,
+}
+
+impl Outline {
+    fn new(source: &str) -> Outline {
+        let mut outline = Outline::default();
+        let mut current: Option<(String, usize)> = None;
+        let lines: Vec<&str> = source.lines().collect();
+        for (index, text) in lines.iter().enumerate() {
+            let line = index + 1;
+            let words = tokens(text);
+            match words.first().map(|(_, word)| *word) {
+                Some("TO") if words.len() > 1 => {
+                    let (column, name) = words[1];
+                    current = Some((name.to_string(), line));
+                    outline
+                        .signatures
+                        .insert(name.to_string(), text.trim().to_string());
+                    outline.add(name, SymbolKind::FUNCTION, line, column, None);
+                    for (column, param) in words[2..].iter() {
+                        let name = name.to_string();
+                        outline.add(
+                            &param.replace('"', ":"),
+                            SymbolKind::VARIABLE,
+                            line,
+                            *column,
+                            Some(name),
+                        );
+                    }
+                }
+                Some("END") => {
+                    if let Some((name, first)) = current.take() {
+                        outline.procedures.insert(name, (first, line));
+                    }
+                }
+                Some("MAKE") if words.len() > 1 => {
+                    let (column, name) = words[1];
+                    let procedure = current.as_ref().map(|(name, _)| name.clone());
+                    let key = name.replace('"', ":");
+                    let known = outline.definitions.iter().any(|definition| {
+                        definition.name == key && definition.procedure == procedure
+                    });
+                    if !known {
+                        outline.add(&key, SymbolKind::VARIABLE, line, column, procedure);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // a procedure without an END carries on to the end of the file
+        if let Some((name, first)) = current {
+            outline.procedures.insert(name, (first, lines.len()));
+        }
+        outline
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        kind: SymbolKind,
+        line: usize,
+        column: usize,
+        procedure: Option<String>,
+    ) {
+        self.definitions.push(Definition {
+            name: name.to_string(),
+            kind,
+            line,
+            column,
+            length: name.chars().count(),
+            procedure,
+        });
+    }
+
+    /// The procedure whose body a line is in
+    fn procedure_at(&self, line: usize) -> Option<String> {
+        self.procedures
+            .iter()
+            .find(|(_, (first, last))| (*first..=*last).contains(&line))
+            .map(|(name, _)| name.clone())
+    }
+
+    fn procedure(&self, name: &str) -> Option<&Definition> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.kind == SymbolKind::FUNCTION && definition.name == name)
+    }
+
+    /// Where a variable used on `line` gets its value, looking in its procedure first
+    fn variable(&self, name: &str, line: usize) -> Option<&Definition> {
+        let procedure = self.procedure_at(line);
+        let find = |procedure: &Option<String>| {
+            self.definitions.iter().find(|definition| {
+                definition.kind == SymbolKind::VARIABLE
+                    && definition.name == name
+                    && definition.procedure == *procedure
+            })
+        };
+        find(&procedure).or_else(|| find(&None))
+    }
+}
+
+/// The 0-based UTF-16 position of a 1-based character column, as editors count them
+fn position(source: &str, line: usize, column: usize) -> Position {
+    let text = source.lines().nth(line - 1).unwrap_or_default();
+    let character: usize = text.chars().take(column - 1).map(char::len_utf16).sum();
+    Position::new(line as u32 - 1, character as u32)
+}
+
+fn range(source: &str, line: usize, column: usize, length: usize) -> Range {
+    Range::new(
+        position(source, line, column),
+        position(source, line, column + length),
+    )
+}
+
+/// The word under an editor's cursor, with its 1-based line and column
+fn word_at(source: &str, position: Position) -> Option<(String, usize, usize)> {
+    let text = source.lines().nth(position.line as usize)?;
+    let mut units = 0;
+    let mut column = text.chars().count() + 1;
+    for (index, c) in text.chars().enumerate() {
+        if units >= position.character as usize {
+            column = index + 1;
+            break;
+        }
+        units += c.len_utf16();
+    }
+    tokens(text)
+        .into_iter()
+        .find(|(start, word)| (*start..=*start + word.chars().count()).contains(&column))
+        .map(|(start, word)| (word.to_string(), position.line as usize + 1, start))
+}
+
+fn describe_type(found: Option<Type>) -> String {
+    match found {
+        Some(found) => found.to_string(),
+        None => "of unknown type".to_string(),
+    }
+}
+
+/// The open files and everything the server works out from them
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        let source = match self.documents.get(uri) {
+            Some(source) => source,
+            None => return Vec::new(),
+        };
+        check(source)
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                range: range(
+                    source,
+                    diagnostic.line,
+                    diagnostic.column,
+                    diagnostic.length,
+                ),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("rslogo".to_string()),
+                message: diagnostic.message,
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let source = self.documents.get(&position.text_document.uri)?;
+        let (word, line, _) = word_at(source, position.position)?;
+        let outline = Outline::new(source);
+        let value =
+            if let Some((_, usage, about)) = BUILTINS.iter().find(|(name, _, _)| *name == word) {
+                format!("```logo\n{}\n```\n{}", usage, about)
+            } else if let Some(signature) = outline.signatures.get(&word) {
+                let mut types = Checker::new(source).types();
+                let mut value = format!("```logo\n{}\n```", signature);
+                for (param, found) in types.param_types(&word) {
+                    value.push_str(&format!("\n- `{}` is {}", param, describe_type(found)));
+                }
+                value
+            } else if word.starts_with(':') || word.starts_with('"') {
+                let name = word.replace('"', ":");
+                let definition = outline.variable(&name, line)?;
+                let mut types = Checker::new(source).types();
+                let found = types.variable_type(definition.procedure.as_deref(), &name);
+                let place = match &definition.procedure {
+                    Some(procedure) if definition.line == outline.procedures[procedure].0 => {
+                        format!("a parameter of {}", procedure)
+                    }
+                    _ => format!("made on line {}", definition.line),
+                };
+                format!("`{}` is {}, {}", name, describe_type(found), place)
+            } else {
+                return None;
+            };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let source = self.documents.get(&uri)?;
+        let (word, line, _) = word_at(source, position.position)?;
+        let outline = Outline::new(source);
+        let definition = match word.starts_with(':') || word.starts_with('"') {
+            true => outline.variable(&word.replace('"', ":"), line)?,
+            false => outline.procedure(&word)?,
+        };
+        let range = range(
+            source,
+            definition.line,
+            definition.column,
+            definition.length,
+        );
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let source = self.documents.get(&position.text_document.uri)?;
+        let typing = word_at(source, position.position).map(|(word, _, _)| word);
+        let line = position.position.line as usize + 1;
+        let outline = Outline::new(source);
+        let mut items = Vec::new();
+        if typing.as_deref().is_some_and(|word| word.starts_with(':')) {
+            let procedure = outline.procedure_at(line);
+            for definition in outline.definitions.iter() {
+                let visible = definition.procedure.is_none() || definition.procedure == procedure;
+                if definition.kind == SymbolKind::VARIABLE && visible {
+                    items.push(CompletionItem {
+                        label: definition.name.clone(),
+                        kind: Some(CompletionItemKind::VARIABLE),
+                        ..CompletionItem::default()
+                    });
+                }
+            }
+            items.dedup_by(|a, b| a.label == b.label);
+        } else {
+            for (name, usage, about) in BUILTINS.iter() {
+                items.push(CompletionItem {
+                    label: name.to_string(),
+                    kind: Some(CompletionItemKind::KEYWORD),
+                    detail: Some(usage.to_string()),
+                    documentation: Some(lsp_types::Documentation::String(about.to_string())),
+                    ..CompletionItem::default()
+                });
+            }
+            for (name, signature) in outline.signatures.iter() {
+                items.push(CompletionItem {
+                    label: name.clone(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    detail: Some(signature.clone()),
+                    ..CompletionItem::default()
+                });
+            }
+        }
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let source = self.documents.get(&params.text_document.uri)?;
+        let outline = Outline::new(source);
+        let symbol =
+            |definition: &Definition, range: Range, children: Option<Vec<DocumentSymbol>>| {
+                let selection = self::range(
+                    source,
+                    definition.line,
+                    definition.column,
+                    definition.length,
+                );
+                // `deprecated` has to be given, even though it is replaced by `tags`
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: definition.name.clone(),
+                    detail: outline.signatures.get(&definition.name).cloned(),
+                    kind: definition.kind,
+                    tags: None,
+                    deprecated: None,
+                    range,
+                    selection_range: selection,
+                    children,
+                }
+            };
+        let mut symbols = Vec::new();
+        for definition in outline.definitions.iter() {
+            let whole_line = |line: usize| {
+                let length = source
+                    .lines()
+                    .nth(line - 1)
+                    .unwrap_or_default()
+                    .chars()
+                    .count();
+                range(source, line, 1, length)
+            };
+            match (&definition.procedure, definition.kind) {
+                (None, SymbolKind::FUNCTION) => {
+                    let (first, last) = outline.procedures[&definition.name];
+                    let children = outline
+                        .definitions
+                        .iter()
+                        .filter(|child| child.procedure.as_ref() == Some(&definition.name))
+                        .map(|child| symbol(child, whole_line(child.line), None))
+                        .collect();
+                    let range = Range::new(whole_line(first).start, whole_line(last).end);
+                    symbols.push(symbol(definition, range, Some(children)));
+                }
+                (None, _) => symbols.push(symbol(definition, whole_line(definition.line), None)),
+                _ => {}
+            }
+        }
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        fn params<P: serde::de::DeserializeOwned>(request: Request) -> Result<P, String> {
+            serde_json::from_value(request.params).map_err(|e| e.to_string())
+        }
+        fn reply<R: serde::Serialize>(request: &Request, result: Result<R, String>) -> Response {
+            match result {
+                Ok(result) => Response::new_ok(request.id.clone(), result),
+                Err(e) => Response::new_err(request.id.clone(), ErrorCode::InvalidParams as i32, e),
+            }
+        }
+        let id = request.clone();
+        match request.method.as_str() {
+            HoverRequest::METHOD => reply(&id, params(request).map(|p| self.hover(p))),
+            GotoDefinition::METHOD => reply(&id, params(request).map(|p| self.definition(p))),
+            Completion::METHOD => reply(&id, params(request).map(|p| self.completion(p))),
+            DocumentSymbolRequest::METHOD => reply(&id, params(request).map(|p| self.symbols(p))),
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("{} is not supported", method),
+            ),
+        }
+    }
+
+    /// Keep track of open files, giving the file whose diagnostics have changed
+    fn handle_notification(&mut self, notification: Notification) -> Result<Option<Url>, String> {
+        fn params<P: serde::de::DeserializeOwned>(notification: Notification) -> Result<P, String> {
+            serde_json::from_value(notification.params).map_err(|e| e.to_string())
+        }
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = params(notification)?;
+                let document = params.text_document;
+                self.documents.insert(document.uri.clone(), document.text);
+                Ok(Some(document.uri))
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = params(notification)?;
+                let uri = params.text_document.uri;
+                // the server asks for the whole file on every change
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                Ok(Some(uri))
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = params(notification)?;
+                self.documents.remove(&params.text_document.uri);
+                Ok(Some(params.text_document.uri))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Serve editors over stdin and stdout until they shut the server down
+pub fn run_lsp() -> Result<(), String> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    // the io threads finish once nothing is left to send to them
+    drop(connection);
+    io_threads.join().map_err(|e| e.to_string())
+}
+
+/// Answer an editor over `connection` until it shuts the server down
+fn serve(connection: &Connection) -> Result<(), String> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".to_string()]),
+            ..CompletionOptions::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let capabilities = serde_json::to_value(capabilities).map_err(|e| e.to_string())?;
+    connection
+        .initialize(capabilities)
+        .map_err(|e| e.to_string())?;
+
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection
+                    .handle_shutdown(&request)
+                    .map_err(|e| e.to_string())?
+                {
+                    break;
+                }
+                let response = server.handle_request(request);
+                connection
+                    .sender
+                    .send(Message::Response(response))
+                    .map_err(|e| e.to_string())?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = server.handle_notification(notification)? {
+                    let params = lsp_types::PublishDiagnosticsParams {
+                        diagnostics: server.diagnostics(&uri),
+                        uri,
+                        version: None,
+                    };
+                    let notification =
+                        Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                    connection
+                        .sender
+                        .send(Message::Notification(notification))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_server::RequestId;
+    use serde_json::{json, Value};
+
+    const SOURCE: &str = "MAKE \"SIZE \"10
+TO SQUARE \"LENGTH
+  FORWARD :LENGTH
+END
+SQUARE :SIZE
+FORWARD :MISSING
+";
+
+    /// An editor talking to a server running on another thread
+    struct Client {
+        connection: Connection,
+        server: std::thread::JoinHandle<Result<(), String>>,
+        next_id: i32,
+    }
+
+    impl Client {
+        /// Start a server, initialise it and open `SOURCE`
+        fn open() -> Client {
+            let (server, connection) = Connection::memory();
+            let server = std::thread::spawn(move || serve(&server));
+            let mut client = Client {
+                connection,
+                server,
+                next_id: 0,
+            };
+            let capabilities = client.request("initialize", json!({ "capabilities": {} }));
+            assert_eq!(capabilities["capabilities"]["hoverProvider"], json!(true));
+            client.notify("initialized", json!({}));
+            client.notify(
+                DidOpenTextDocument::METHOD,
+                json!({ "textDocument": {
+                    "uri": "file:///square.lg",
+                    "languageId": "logo",
+                    "version": 1,
+                    "text": SOURCE,
+                } }),
+            );
+            client
+        }
+
+        fn notify(&self, method: &str, params: Value) {
+            let notification = Notification::new(method.to_string(), params);
+            self.connection
+                .sender
+                .send(Message::Notification(notification))
+                .unwrap();
+        }
+
+        fn receive(&self) -> Message {
+            self.connection
+                .receiver
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap()
+        }
+
+        /// Send a request and wait for its result
+        fn request(&mut self, method: &str, params: Value) -> Value {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), method.to_string(), params);
+            self.connection
+                .sender
+                .send(Message::Request(request))
+                .unwrap();
+            match self.receive() {
+                Message::Response(response) => {
+                    assert_eq!(response.id, id);
+                    assert!(response.error.is_none(), "{:?}", response.error);
+                    response.result.unwrap_or(Value::Null)
+                }
+                message => panic!("expected a response, got {:?}", message),
+            }
+        }
+
+        /// The request's parameters for a place in `SOURCE`
+        fn at(line: u32, character: u32) -> Value {
+            json!({
+                "textDocument": { "uri": "file:///square.lg" },
+                "position": { "line": line, "character": character },
+            })
+        }
+
+        fn shut_down(mut self) {
+            assert_eq!(self.request("shutdown", Value::Null), Value::Null);
+            self.notify("exit", Value::Null);
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn opening_a_file_publishes_its_diagnostics() {
+        let client = Client::open();
+        let notification = match client.receive() {
+            Message::Notification(notification) => notification,
+            message => panic!("expected diagnostics, got {:?}", message),
+        };
+        assert_eq!(notification.method, PublishDiagnostics::METHOD);
+        let params: lsp_types::PublishDiagnosticsParams =
+            serde_json::from_value(notification.params).unwrap();
+        assert_eq!(params.uri.as_str(), "file:///square.lg");
+        assert_eq!(params.diagnostics.len(), 1);
+        let diagnostic = &params.diagnostics[0];
+        assert_eq!(diagnostic.message, ":MISSING is not defined");
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(5, 8), Position::new(5, 16))
+        );
+        client.shut_down();
+    }
+
+    #[test]
+    fn hover_describes_builtins_procedures_and_variables() {
+        let mut client = Client::open();
+        client.receive();
+
+        let hover: Hover =
+            serde_json::from_value(client.request(HoverRequest::METHOD, Client::at(5, 2))).unwrap();
+        let markdown = |hover: Hover| match hover.contents {
+            HoverContents::Markup(markup) => markup.value,
+            contents => panic!("expected markdown, got {:?}", contents),
+        };
+        assert!(markdown(hover).contains("FORWARD distance"));
+
+        let hover = client.request(HoverRequest::METHOD, Client::at(4, 2));
+        assert!(markdown(serde_json::from_value(hover).unwrap()).contains("TO SQUARE \"LENGTH"));
+
+        let hover = client.request(HoverRequest::METHOD, Client::at(4, 9));
+        assert!(markdown(serde_json::from_value(hover).unwrap()).contains("made on line 1"));
+
+        // nothing to say about a number
+        assert_eq!(
+            client.request(HoverRequest::METHOD, Client::at(0, 12)),
+            Value::Null
+        );
+        client.shut_down();
+    }
+
+    #[test]
+    fn definition_finds_procedures_and_variables() {
+        let mut client = Client::open();
+        client.receive();
+        let definition = |client: &mut Client, line, character| {
+            let response = client.request(GotoDefinition::METHOD, Client::at(line, character));
+            match serde_json::from_value(response).unwrap() {
+                GotoDefinitionResponse::Scalar(location) => location.range.start,
+                response => panic!("expected one location, got {:?}", response),
+            }
+        };
+        // SQUARE is called on line 5 and defined on line 2
+        assert_eq!(definition(&mut client, 4, 2).line, 1);
+        // :SIZE is made on line 1
+        assert_eq!(definition(&mut client, 4, 9).line, 0);
+        // :LENGTH is a parameter of SQUARE
+        assert_eq!(definition(&mut client, 2, 11).line, 1);
+        client.shut_down();
+    }
+
+    #[test]
+    fn unknown_requests_are_refused() {
+        let mut client = Client::open();
+        client.receive();
+        client.next_id += 1;
+        let id = RequestId::from(client.next_id);
+        let request = Request::new(id, "textDocument/rename".to_string(), Value::Null);
+        client
+            .connection
+            .sender
+            .send(Message::Request(request))
+            .unwrap();
+        match client.receive() {
+            Message::Response(response) => {
+                assert_eq!(
+                    response.error.map(|error| error.code),
+                    Some(ErrorCode::MethodNotFound as i32)
+                )
+            }
+            message => panic!("expected a response, got {:?}", message),
+        }
+        client.shut_down();
+    }
+}
@@ -0,0 +1,181 @@
+use crate::operations::Operation;
+use crate::utils::extract_operations;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The most symbols an L-system may be rewritten through, counting every
+/// iteration. Each iteration of a rule such as `F=FF` doubles the length,
+/// so without a bound a small number of iterations could use up all memory.
+pub const MAX_SYMBOLS: usize = 1_000_000;
+
+/// An L-system: an axiom that is rewritten by production rules
+/// and then drawn by the turtle, one symbol at a time.
+///
+/// The turtle understands these symbols:
+///  - `F`, `G`: move forward by `step`, drawing if the pen is down
+///  - `f`: move forward by `step` without drawing
+///  - `+`: turn left by `angle`
+///  - `-`: turn right by `angle`
+///  - `[`, `]`: PUSHTURTLE and POPTURTLE
+///
+/// Any other symbol is only used by the rules and is ignored when drawing.
+#[derive(Debug, Clone, Serialize)]
+pub struct LSystem {
+    pub axiom: String,
+    pub rules: Vec<(char, String)>,
+    pub iterations: Operation,
+    pub angle: Operation,
+    pub step: Operation,
+}
+
+impl LSystem {
+    /// Rewrite the axiom `iterations` times, refusing if that would take
+    /// more than `MAX_SYMBOLS` symbols
+    pub fn expand(&self, iterations: usize) -> Result<String, String> {
+        if self.rewritten_length(iterations) > MAX_SYMBOLS {
+            return Err(format!(
+                "LSYSTEM: {} iterations would make more than {} symbols",
+                iterations, MAX_SYMBOLS
+            ));
+        }
+        let mut current = self.axiom.clone();
+        for _ in 0..iterations {
+            let mut next = String::with_capacity(current.len());
+            for symbol in current.chars() {
+                match self.rules.iter().find(|(from, _)| *from == symbol) {
+                    Some((_, to)) => next.push_str(to),
+                    None => next.push(symbol),
+                }
+            }
+            current = next;
+        }
+        Ok(current)
+    }
+
+    /// How many symbols rewriting the axiom `iterations` times goes through,
+    /// counting every rewrite, worked out from how many of each symbol there
+    /// are rather than by building the strings. Stops once past `MAX_SYMBOLS`.
+    fn rewritten_length(&self, iterations: usize) -> usize {
+        let mut counts: HashMap<char, usize> = HashMap::new();
+        for symbol in self.axiom.chars() {
+            *counts.entry(symbol).or_default() += 1;
+        }
+        let mut total = self.axiom.chars().count();
+        for _ in 0..iterations {
+            let mut next: HashMap<char, usize> = HashMap::new();
+            for (symbol, count) in counts {
+                match self.rules.iter().find(|(from, _)| *from == symbol) {
+                    Some((_, to)) => {
+                        for symbol in to.chars() {
+                            let entry = next.entry(symbol).or_default();
+                            *entry = entry.saturating_add(count);
+                        }
+                    }
+                    None => {
+                        let entry = next.entry(symbol).or_default();
+                        *entry = entry.saturating_add(count);
+                    }
+                }
+            }
+            counts = next;
+            let length = counts
+                .values()
+                .fold(0, |sum: usize, count| sum.saturating_add(*count));
+            total = total.saturating_add(length);
+            // an empty string stays empty, however many times it is rewritten
+            if length == 0 || total > MAX_SYMBOLS {
+                break;
+            }
+        }
+        total
+    }
+
+    /// Build an L-system from `LSYSTEM "axiom "iterations "angle "step "rule...`
+    pub fn from_command(parts: &[&str]) -> Result<LSystem, String> {
+        if parts.len() < 4 {
+            return Err("LSYSTEM command has wrong number of arguments".to_string());
+        }
+        let axiom = parts[0].strip_prefix('\"').unwrap_or(parts[0]).to_string();
+        let rules = parts[4..]
+            .iter()
+            .map(|rule| parse_rule(rule.strip_prefix('\"').unwrap_or(rule)))
+            .collect::<Result<Vec<(char, String)>, String>>()?;
+
+        Ok(LSystem {
+            axiom,
+            rules,
+            iterations: extract_operations(&parts[1..2])?,
+            angle: extract_operations(&parts[2..3])?,
+            step: extract_operations(&parts[3..4])?,
+        })
+    }
+
+    /// Build an L-system from a rule file, which has one setting per line:
+    ///
+    /// ```text
+    /// // Fractal plant
+    /// axiom X
+    /// rule X=F+[[X]-X]-F[-FX]+X
+    /// rule F=FF
+    /// iterations 5
+    /// angle 25
+    /// step 4
+    /// ```
+    pub fn from_rule_file(content: &str) -> Result<LSystem, String> {
+        let mut axiom: Option<String> = None;
+        let mut rules: Vec<(char, String)> = Vec::new();
+        let mut iterations = "\"1".to_string();
+        let mut angle = "\"90".to_string();
+        let mut step = "\"10".to_string();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with("//") || line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(char::is_whitespace)
+                .map(|(key, value)| (key, value.trim()))
+                .ok_or_else(|| format!("LSYSTEM rule file: missing value in `{}`", line))?;
+            match key {
+                "axiom" => axiom = Some(value.to_string()),
+                "rule" => rules.push(parse_rule(value)?),
+                "iterations" | "angle" | "step" => {
+                    if value.parse::<f32>().is_err() {
+                        return Err(format!("LSYSTEM rule file: {} is not a number", key));
+                    }
+                    let value = format!("{}{}", '\"', value);
+                    match key {
+                        "iterations" => iterations = value,
+                        "angle" => angle = value,
+                        _ => step = value,
+                    }
+                }
+                _ => return Err(format!("LSYSTEM rule file: unknown setting {}", key)),
+            }
+        }
+
+        Ok(LSystem {
+            axiom: axiom.ok_or_else(|| "LSYSTEM rule file: missing axiom".to_string())?,
+            rules,
+            iterations: Operation::Base(iterations),
+            angle: Operation::Base(angle),
+            step: Operation::Base(step),
+        })
+    }
+}
+
+/// Parse a production rule written as `F=F+F`
+fn parse_rule(rule: &str) -> Result<(char, String), String> {
+    let (from, to) = rule
+        .split_once('=')
+        .ok_or_else(|| format!("LSYSTEM rule {} has no =", rule))?;
+    let mut symbols = from.chars();
+    match (symbols.next(), symbols.next()) {
+        (Some(symbol), None) => Ok((symbol, to.to_string())),
+        _ => Err(format!(
+            "LSYSTEM rule {} must rewrite a single symbol",
+            rule
+        )),
+    }
+}
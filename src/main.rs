@@ -1,38 +1,91 @@
-use rslogo::utils::{Args, QueriesStruct, extract_commands};
+use rslogo::utils::{Args, Emit, QueriesStruct, Tool, extract_commands};
+use rslogo::repl::run_repl;
+use rslogo::check::run_check;
+use rslogo::formatter::run_fmt;
+use rslogo::ast::run_ast;
+use rslogo::lsp::run_lsp;
+use rslogo::lint::run_lint;
 use std::collections::HashMap;
 use rslogo::procedures::DummyProcedure;
-use rslogo::executer::execute_command;
-use unsvg::Image;
-use clap::Parser;
-use std::fs::read_to_string;
+use rslogo::executer::{draw_turtle, execute_statement, Observer};
+use rslogo::debugger::{run_debugger, Breakpoint, Debugger};
+use rslogo::animation::{
+    default_steps_per_frame, save_gif, save_png_frames, to_animated_svg, AnimationOptions,
+};
+use rslogo::backend::{RecordingBackend, RenderBackend};
+use rslogo::canvas::{Canvas, RasterOptions};
+use rslogo::display_list::DisplayList;
+use rslogo::dxf::to_dxf;
+use rslogo::document::{to_eps, to_pdf, PageOptions};
+use rslogo::plotter::{to_gcode, to_hpgl, PlotterOptions};
+use rslogo::terminal::{to_terminal, TerminalOptions};
+use rslogo::trace::Tracer;
+use rslogo::limits::{LimitObserver, Limits};
+use rslogo::types::infer_program;
+use clap::{Parser, Subcommand};
+use std::fs::{read_to_string, File};
+use std::io::{BufWriter, Write};
 
-fn main() -> Result<(), ()> {
-    let args: Args = Args::parse();
+fn main() {
+    // `rslogo repl` and the other tools, otherwise run a file
+    if std::env::args()
+        .nth(1)
+        .is_some_and(|name| Tool::has_subcommand(&name))
+    {
+        let result = match Tool::parse() {
+            Tool::Repl(args) => run_repl(args),
+            Tool::Debug(args) => run_debugger(args),
+            Tool::Check(args) => run_check(args),
+            Tool::Fmt(args) => run_fmt(args),
+            Tool::Ast(args) => run_ast(args),
+            Tool::Lsp => run_lsp(),
+            Tool::Lint(args) => run_lint(args),
+        };
+        if let Err(e) = result {
+            eprintln!("\x1b[31m{}\x1b[0m", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let args: Args = Args::parse_command_line();
 
     let file_path = args.file_path;
     let image_path = args.image_path;
-    let height = args.height;
-    let width = args.width;
+    let height = args.height.expect("checked when parsing");
+    let width = args.width.expect("checked when parsing");
+    let background = match unsvg::COLORS.get(args.background) {
+        Some(color) => *color,
+        None => {
+            eprintln!("Background colour must be between 0 and 15");
+            std::process::exit(1);
+        }
+    };
 
-    let file_content = match read_to_string(file_path) {
+    // svg user units are css pixels, at 96 to the inch
+    let raster = RasterOptions {
+        scale: args.dpi.map_or(args.scale, |dpi| dpi / 96.0),
+        antialias: !args.no_antialias,
+        dpi: args.dpi,
+    };
+    if !(raster.scale > 0.0 && raster.scale.is_finite()) {
+        eprintln!("Scale and dpi must be greater than 0");
+        std::process::exit(1);
+    }
+
+    let file_content = match read_to_string(&file_path) {
         Ok(content) => content,
-        Err(_) => {
-            return Err(());
+        Err(e) => {
+            eprintln!("Error reading {}: {e}", file_path.display());
+            std::process::exit(1);
         }
     };
 
     let mut dummy_procedures: HashMap<String, DummyProcedure> = HashMap::new();
     let mut variable_table: HashMap<String, String> = HashMap::new();
 
-    let mut lines: Vec<&str> = Vec::new();
-
-    for line in file_content.lines() {
-        let line = line.trim();
-        if line.starts_with("// ") || line.is_empty() {
-            continue;
-        }
-        lines.push(line);
-    }
+    // comments and empty lines are kept, so statements know their line numbers
+    let lines: Vec<&str> = file_content.lines().map(|line| line.trim()).collect();
 
     let commands = match extract_commands(&lines, 0, &mut dummy_procedures) {
         Ok((commands, _)) => commands,
@@ -42,52 +95,232 @@ fn main() -> Result<(), ()> {
         }
     };
 
-    let mut image = Image::new(width, height);
+    // values of the wrong type would otherwise only be found part way through drawing.
+    // Only stop on ones sure to fail; `rslogo check` reports the rest.
+    let inference = infer_program(&commands, &dummy_procedures);
+    if let Some(error) = inference.errors.iter().find(|error| error.certain) {
+        eprintln!(
+            "\x1b[31mError processing commands: line {}: {}\x1b[0m",
+            error.line, error.message
+        );
+        std::process::exit(1);
+    }
+
+    // Record the drawing once, then replay it onto whichever output is needed
+    let mut image = RecordingBackend::new(width, height);
+    image.set_background(background);
+
+    let mut queries_struct = QueriesStruct::new(width, height);
+    // LSYSTEMFILE paths are relative to the program, not to where rslogo is run
+    queries_struct.source_dir = file_path
+        .parent()
+        .map(|dir| dir.to_path_buf())
+        .unwrap_or_default();
 
-    let mut queries_struct = QueriesStruct {
-        xcor: format!("{}{}", "\"", (width / 2)),
-        ycor: format!("{}{}", "\"", (height / 2)),
-        heading: "\"0".to_string(),
-        color: "\"7".to_string(),
-        is_pen_down: "FALSE".to_string(),
+    let mut observers: Vec<Box<dyn Observer>> = Vec::new();
+    let limits = Limits {
+        max_steps: args.max_steps,
+        max_depth: args.max_depth,
+        timeout: args.timeout,
+        max_segments: args.max_segments,
     };
+    if !limits.is_empty() {
+        observers.push(Box::new(LimitObserver::new(limits)));
+    }
+    if args.trace {
+        let out: Box<dyn Write> = match &args.trace_file {
+            Some(path) => match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(e) => {
+                    eprintln!("Error creating trace file: {e}");
+                    std::process::exit(1);
+                }
+            },
+            None => Box::new(std::io::stderr()),
+        };
+        observers.push(Box::new(Tracer::new(out, args.trace_format)));
+    }
+    if args.debug {
+        let breakpoints = args.breakpoints.iter().map(|value| Breakpoint::parse(value)).collect();
+        observers.push(Box::new(Debugger::new(&file_content, breakpoints)));
+    }
 
     let mut map: HashMap<String, String> = HashMap::new();
-    for command in commands.iter() {
-        if let Err(e) = execute_command(
-            command,
+    for statement in commands.iter() {
+        if let Err(e) = execute_statement(
+            statement,
             &mut variable_table,
             &mut dummy_procedures,
             false,
             &mut map,
             &mut queries_struct,
             &mut image,
+            &mut observers,
         ) {
+            // the trace file is buffered, so finish writing it before exiting
+            drop(observers);
             eprintln!("\x1b[31mError processing commands: {}\x1b[0m", e);
             std::process::exit(1);
         }
     }
+    drop(observers);
+
+    if let Err(e) = draw_turtle(&queries_struct, &mut image) {
+        eprintln!("\x1b[31mError processing commands: {}\x1b[0m", e);
+        std::process::exit(1);
+    }
+
+    match args.emit {
+        Some(Emit::Json) => {
+            let mut display_list = DisplayList::new(width, height);
+            image.replay(&mut display_list);
+            match display_list.to_json() {
+                Ok(json) => println!("{json}"),
+                Err(e) => {
+                    eprintln!("Error writing json: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Emit::Terminal) => {
+            let mut canvas = Canvas::new(width, height);
+            image.replay(&mut canvas);
+            if args.fit {
+                canvas.fit(args.margin);
+            } else if args.crop {
+                canvas.crop(args.margin);
+            }
+            let options = TerminalOptions {
+                columns: args.terminal_width,
+                style: args.terminal_style,
+                color: std::env::var_os("NO_COLOR").is_none(),
+            };
+            match to_terminal(&canvas, options) {
+                Ok(preview) => print!("{preview}"),
+                Err(e) => {
+                    eprintln!("Error writing terminal preview: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {}
+    }
+
+    // --emit on its own only prints the drawing
+    let image_path = match image_path {
+        Some(image_path) => image_path,
+        None => return,
+    };
+
+    let animation = AnimationOptions {
+        steps_per_frame: args
+            .steps_per_frame
+            .unwrap_or_else(|| default_steps_per_frame(&image)),
+        frame_delay: args.frame_delay,
+        raster,
+    };
 
     match image_path.extension().and_then(|s| s.to_str()) {
+        Some("svg") if args.animate => {
+            if let Err(e) = std::fs::write(&image_path, to_animated_svg(&image, animation)) {
+                eprintln!("Error saving svg: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some("png") if args.animate => {
+            if let Err(e) = save_png_frames(&image, &image_path, animation) {
+                eprintln!("Error saving png frames: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some("gif") => {
+            if let Err(e) = save_gif(&image, &image_path, animation) {
+                eprintln!("Error saving gif: {e}");
+                std::process::exit(1);
+            }
+        }
         Some("svg") => {
-            let res = image.save_svg(&image_path);
+            let mut canvas = Canvas::new(width, height);
+            image.replay(&mut canvas);
+            if args.fit {
+                canvas.fit(args.margin);
+            } else if args.crop {
+                canvas.crop(args.margin);
+            }
+            let res = canvas.save_svg(&image_path, args.svg_precision);
             if let Err(e) = res {
                 eprintln!("Error saving svg: {e}");
-                return Err(());
+                std::process::exit(1);
             }
         }
         Some("png") => {
-            let res = image.save_png(&image_path);
+            let mut canvas = Canvas::new(width, height);
+            image.replay(&mut canvas);
+            if args.fit {
+                canvas.fit(args.margin);
+            } else if args.crop {
+                canvas.crop(args.margin);
+            }
+            let res = canvas.save_png(&image_path, raster);
             if let Err(e) = res {
                 eprintln!("Error saving png: {e}");
-                return Err(());
+                std::process::exit(1);
+            }
+        }
+        Some("hpgl") | Some("gcode") => {
+            let mut display_list = DisplayList::new(width, height);
+            image.replay(&mut display_list);
+            let options = PlotterOptions {
+                mm_per_pixel: args.mm_per_pixel,
+                feed_rate: args.feed_rate,
+            };
+            let output = if image_path.extension().and_then(|s| s.to_str()) == Some("hpgl") {
+                to_hpgl(&display_list, options)
+            } else {
+                to_gcode(&display_list, options)
+            };
+            if let Err(e) = std::fs::write(&image_path, output) {
+                eprintln!("Error saving plotter output: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some("pdf") | Some("eps") => {
+            let mut display_list = DisplayList::new(width, height);
+            image.replay(&mut display_list);
+            let page = PageOptions {
+                width: args.page_width.unwrap_or(width as f32),
+                height: args.page_height.unwrap_or(height as f32),
+            };
+            let output = if image_path.extension().and_then(|s| s.to_str()) == Some("pdf") {
+                to_pdf(&display_list, page)
+            } else {
+                to_eps(&display_list, page)
+            };
+            if let Err(e) = std::fs::write(&image_path, output) {
+                eprintln!("Error saving document: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some("dxf") => {
+            let mut display_list = DisplayList::new(width, height);
+            image.replay(&mut display_list);
+            if let Err(e) = std::fs::write(&image_path, to_dxf(&display_list)) {
+                eprintln!("Error saving dxf: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some("json") => {
+            let mut display_list = DisplayList::new(width, height);
+            image.replay(&mut display_list);
+            let res = display_list.save_json(&image_path);
+            if let Err(e) = res {
+                eprintln!("Error saving json: {e}");
+                std::process::exit(1);
             }
         }
         _ => {
             eprintln!("File extension not supported");
-            return Err(());
+            std::process::exit(1);
         }
     }
-
-    Ok(())
 }
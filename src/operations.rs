@@ -1,4 +1,7 @@
-#[derive(Debug, Clone)]
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
 pub enum Operation {
     Base(String),
     Add(Box<Operation>, Box<Operation>),
@@ -12,3 +15,33 @@ pub enum Operation {
     And(Box<Operation>, Box<Operation>),
     Or(Box<Operation>, Box<Operation>),
 }
+
+impl Operation {
+    /// The operator's word and its two values, or `None` for a single value
+    pub fn operator(&self) -> Option<(&'static str, &Operation, &Operation)> {
+        match self {
+            Operation::Base(_) => None,
+            Operation::Add(a, b) => Some(("+", a, b)),
+            Operation::Subtract(a, b) => Some(("-", a, b)),
+            Operation::Multiply(a, b) => Some(("*", a, b)),
+            Operation::Divide(a, b) => Some(("/", a, b)),
+            Operation::Equal(a, b) => Some(("EQ", a, b)),
+            Operation::Notequal(a, b) => Some(("NE", a, b)),
+            Operation::Greaterthan(a, b) => Some(("GT", a, b)),
+            Operation::Lessthan(a, b) => Some(("LT", a, b)),
+            Operation::And(a, b) => Some(("AND", a, b)),
+            Operation::Or(a, b) => Some(("OR", a, b)),
+        }
+    }
+}
+
+/// Written back as Logo, in prefix order: `+ "3 * "4 "5`
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.operator(), self) {
+            (Some((name, a, b)), _) => write!(f, "{} {} {}", name, a, b),
+            (None, Operation::Base(word)) => write!(f, "{}", word),
+            (None, _) => unreachable!(), // only a Base has no operator
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::commands::Command;
+use crate::lsystem::LSystem;
 use crate::operations::Operation;
 use crate::procedures::DummyProcedure;
 use crate::utils::{QueriesStruct, extract_operations};
@@ -12,13 +13,24 @@ pub fn parse_command(
   let parts: Vec<&str> = line.split_whitespace().collect();
 
   match parts[0] {
-      "PENUP" | "PENDOWN" if parts.len() == 1 => Ok(match parts[0] {
-          "PENUP" => Command::Penup,
-          "PENDOWN" => Command::Pendown,
-          _ => unreachable!(), // We won't get here
-      }),
-      "FORWARD" | "BACK" | "RIGHT" | "LEFT" | "SETPENCOLOR" | "TURN" | "SETHEADING" | "SETX"
-      | "SETY"
+      "PENUP" | "PENDOWN" | "SHOWTURTLE" | "HIDETURTLE" | "PUSHTURTLE" | "POPTURTLE"
+      | "CLEARSCREEN" | "CLEAN"
+          if parts.len() == 1 =>
+      {
+          Ok(match parts[0] {
+              "PENUP" => Command::Penup,
+              "PENDOWN" => Command::Pendown,
+              "SHOWTURTLE" => Command::Showturtle,
+              "HIDETURTLE" => Command::Hideturtle,
+              "PUSHTURTLE" => Command::Pushturtle,
+              "POPTURTLE" => Command::Popturtle,
+              "CLEARSCREEN" => Command::Clearscreen,
+              "CLEAN" => Command::Clean,
+              _ => unreachable!(), // We won't get here
+          })
+      }
+      "FORWARD" | "BACK" | "RIGHT" | "LEFT" | "SETPENCOLOR" | "SETBACKGROUND" | "SETPENALPHA"
+      | "SETFILLALPHA" | "TURN" | "SETHEADING" | "SETX" | "SETY"
           if parts.len() > 1 =>
       {
           let operations = parts[1..].to_vec();
@@ -30,6 +42,9 @@ pub fn parse_command(
               "RIGHT" => Command::Right(extracted),
               "LEFT" => Command::Left(extracted),
               "SETPENCOLOR" => Command::Setpencolor(extracted),
+              "SETBACKGROUND" => Command::Setbackground(extracted),
+              "SETPENALPHA" => Command::Setpenalpha(extracted),
+              "SETFILLALPHA" => Command::Setfillalpha(extracted),
               "TURN" => Command::Turn(extracted),
               "SETHEADING" => Command::Setheading(extracted),
               "SETX" => Command::Setx(extracted),
@@ -48,6 +63,11 @@ pub fn parse_command(
               _ => unreachable!(), // We won't get here
           })
       }
+      "LSYSTEM" => Ok(Command::Lsystem(LSystem::from_command(&parts[1..])?)),
+      "LSYSTEMFILE" if parts.len() == 2 => {
+          let path = parts[1].strip_prefix('\"').unwrap_or(parts[1]);
+          Ok(Command::Lsystemfile(path.to_string()))
+      }
       _ if dummy_procedures.contains_key(parts[0]) => {
           let args: Vec<String> = parts
               .get(1..)
@@ -72,7 +92,7 @@ pub fn parse_command(
 pub fn parse_operation(
   operation: &Operation,
   variable_table: &HashMap<String, String>,
-  queries: &mut QueriesStruct,
+  queries: &QueriesStruct,
 ) -> Result<String, String> {
   match operation {
       Operation::Base(raw_value) => match raw_value.chars().next() {
@@ -0,0 +1,251 @@
+use crate::backend::arc_points;
+use crate::display_list::{DisplayItem, DisplayList};
+
+/// Height the tool is lifted to between strokes, in millimetres
+pub const PEN_UP_Z: f32 = 5.0;
+/// Height of the tool while drawing, in millimetres
+pub const PEN_DOWN_Z: f32 = 0.0;
+
+/// A connected run of lines drawn with a single pen
+#[derive(Debug, Clone)]
+pub struct Stroke {
+    pub color: String,
+    pub points: Vec<(f32, f32)>,
+}
+
+/// Settings shared by the plotter writers
+#[derive(Debug, Clone, Copy)]
+pub struct PlotterOptions {
+    /// Size of one image pixel on paper
+    pub mm_per_pixel: f32,
+    /// Drawing speed in millimetres per minute (G-code only)
+    pub feed_rate: f32,
+}
+
+/// Turn the display list into strokes a plotter can follow.
+/// Touching segments are joined, arcs become short lines,
+/// filled shapes are outlined and labels are skipped.
+pub fn strokes(display_list: &DisplayList) -> Vec<Stroke> {
+    let mut strokes: Vec<Stroke> = Vec::new();
+
+    for item in display_list.items.iter() {
+        match item {
+            DisplayItem::Segment { from, to, pen, .. } => match strokes.last_mut() {
+                Some(stroke) if stroke.color == pen.color && stroke.points.last() == Some(from) => {
+                    stroke.points.push(*to);
+                }
+                _ => strokes.push(Stroke {
+                    color: pen.color.clone(),
+                    points: vec![*from, *to],
+                }),
+            },
+            DisplayItem::Arc {
+                center,
+                radius,
+                start,
+                sweep,
+                pen,
+                ..
+            } => strokes.push(Stroke {
+                color: pen.color.clone(),
+                points: arc_points(*center, *radius, *start, *sweep),
+            }),
+            DisplayItem::Fill { points, pen, .. } => {
+                let mut points = points.clone();
+                if let Some(first) = points.first() {
+                    points.push(*first);
+                }
+                strokes.push(Stroke {
+                    color: pen.color.clone(),
+                    points,
+                });
+            }
+            DisplayItem::Label { .. } => {}
+        }
+    }
+    strokes
+}
+
+/// Reorder strokes so the pen travels as little as possible while lifted.
+/// Strokes are grouped by colour, so each pen is only picked up once,
+/// then the nearest stroke (from either end) is always drawn next.
+pub fn order_strokes(strokes: Vec<Stroke>) -> Vec<Stroke> {
+    let mut colors: Vec<String> = Vec::new();
+    for stroke in strokes.iter() {
+        if !colors.contains(&stroke.color) {
+            colors.push(stroke.color.clone());
+        }
+    }
+
+    let mut ordered: Vec<Stroke> = Vec::new();
+    let mut position = (0.0, 0.0);
+    for color in colors.iter() {
+        let mut remaining: Vec<Stroke> = strokes
+            .iter()
+            .filter(|stroke| stroke.color == *color)
+            .cloned()
+            .collect();
+        while !remaining.is_empty() {
+            let mut best = (0, false, f32::MAX);
+            for (index, stroke) in remaining.iter().enumerate() {
+                let start = distance(position, stroke.points[0]);
+                let end = distance(position, stroke.points[stroke.points.len() - 1]);
+                if start < best.2 {
+                    best = (index, false, start);
+                }
+                if end < best.2 {
+                    best = (index, true, end);
+                }
+            }
+            let mut stroke = remaining.swap_remove(best.0);
+            if best.1 {
+                stroke.points.reverse();
+            }
+            position = stroke.points[stroke.points.len() - 1];
+            ordered.push(stroke);
+        }
+    }
+    ordered
+}
+
+/// Write HPGL, using a separate pen number for each colour
+pub fn to_hpgl(display_list: &DisplayList, options: PlotterOptions) -> String {
+    // HPGL works in plotter units of 0.025mm, with y going up
+    let units = |point: (f32, f32)| {
+        (
+            (point.0 * options.mm_per_pixel * 40.0).round() as i32,
+            ((display_list.height as f32 - point.1) * options.mm_per_pixel * 40.0).round() as i32,
+        )
+    };
+
+    let mut hpgl = String::from("IN;\n");
+    let mut colors: Vec<String> = Vec::new();
+    for stroke in order_strokes(strokes(display_list)) {
+        if colors.last() != Some(&stroke.color) {
+            colors.push(stroke.color.clone());
+            hpgl.push_str(&format!("SP{};\n", colors.len()));
+        }
+        let (x, y) = units(stroke.points[0]);
+        hpgl.push_str(&format!("PU{},{};\n", x, y));
+        let points: Vec<String> = stroke.points[1..]
+            .iter()
+            .map(|point| {
+                let (x, y) = units(*point);
+                format!("{},{}", x, y)
+            })
+            .collect();
+        hpgl.push_str(&format!("PD{};\n", points.join(",")));
+    }
+    hpgl.push_str("PU;\nSP0;\n");
+    hpgl
+}
+
+/// Write G-code, lifting the tool on the Z axis between strokes
+pub fn to_gcode(display_list: &DisplayList, options: PlotterOptions) -> String {
+    // machine coordinates have y going up
+    let millimetres = |point: (f32, f32)| {
+        (
+            point.0 * options.mm_per_pixel,
+            (display_list.height as f32 - point.1) * options.mm_per_pixel,
+        )
+    };
+
+    let mut gcode = String::from("G21 ; millimetres\nG90 ; absolute positions\n");
+    gcode.push_str(&format!("G0 Z{:.3}\n", PEN_UP_Z));
+    let mut color: Option<String> = None;
+    for stroke in order_strokes(strokes(display_list)) {
+        if color.as_ref() != Some(&stroke.color) {
+            gcode.push_str(&format!("; pen {}\n", stroke.color));
+            color = Some(stroke.color.clone());
+        }
+        let (x, y) = millimetres(stroke.points[0]);
+        gcode.push_str(&format!("G0 X{:.3} Y{:.3}\n", x, y));
+        gcode.push_str(&format!("G1 Z{:.3} F{}\n", PEN_DOWN_Z, options.feed_rate));
+        for point in stroke.points[1..].iter() {
+            let (x, y) = millimetres(*point);
+            gcode.push_str(&format!("G1 X{:.3} Y{:.3} F{}\n", x, y, options.feed_rate));
+        }
+        gcode.push_str(&format!("G0 Z{:.3}\n", PEN_UP_Z));
+    }
+    gcode.push_str("G0 X0 Y0\n");
+    gcode
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{RecordingBackend, RenderBackend, Style};
+
+    /// Two white strokes, drawn far one first, then a red one
+    fn drawing() -> DisplayList {
+        let mut recording = RecordingBackend::new(100, 100);
+        recording.set_style(Style::default());
+        recording.move_to((60.0, 10.0));
+        recording.line_to((90.0, 10.0));
+        recording.set_style(Style {
+            color: unsvg::COLORS[4],
+            ..Style::default()
+        });
+        recording.move_to((5.0, 5.0));
+        recording.line_to((5.0, 50.0));
+        recording.set_style(Style::default());
+        recording.move_to((30.0, 10.0));
+        recording.line_to((10.0, 10.0));
+        let mut display_list = DisplayList::new(100, 100);
+        recording.replay(&mut display_list);
+        display_list
+    }
+
+    const OPTIONS: PlotterOptions = PlotterOptions {
+        mm_per_pixel: 1.0,
+        feed_rate: 1500.0,
+    };
+
+    #[test]
+    fn hpgl_draws_each_colour_once_taking_the_nearest_stroke_next() {
+        // the second white stroke is nearest the origin at its far end, so it is drawn backwards
+        let expected = "IN;\nSP1;\nPU400,3600;\nPD1200,3600;\nPU2400,3600;\nPD3600,3600;\nSP2;\nPU200,3800;\nPD200,2000;\nPU;\nSP0;\n";
+        assert_eq!(to_hpgl(&drawing(), OPTIONS), expected);
+    }
+
+    #[test]
+    fn gcode_lifts_the_tool_before_every_travel_move() {
+        let gcode = to_gcode(&drawing(), OPTIONS);
+        let lines: Vec<&str> = gcode.lines().collect();
+        assert_eq!(
+            lines[..3],
+            ["G21 ; millimetres", "G90 ; absolute positions", "G0 Z5.000"]
+        );
+        assert_eq!(lines[lines.len() - 1], "G0 X0 Y0");
+
+        let mut pen_down = false;
+        let mut travels = Vec::new();
+        for line in lines.iter() {
+            if line.starts_with("G1 Z") {
+                pen_down = true;
+            } else if line.starts_with("G0 Z") {
+                pen_down = false;
+            } else if line.starts_with("G0 X") {
+                assert!(!pen_down, "travelled with the tool down: {}", line);
+                travels.push(*line);
+            } else if line.starts_with("G1 X") {
+                assert!(pen_down, "drew with the tool up: {}", line);
+            }
+        }
+        assert_eq!(
+            travels,
+            [
+                "G0 X10.000 Y90.000",
+                "G0 X60.000 Y90.000",
+                "G0 X5.000 Y95.000",
+                "G0 X0 Y0"
+            ]
+        );
+        assert!(gcode.contains("; pen #ffffff\n"));
+        assert!(gcode.contains("G1 X30.000 Y90.000 F1500\nG0 Z5.000\n"));
+    }
+}
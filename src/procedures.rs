@@ -1,7 +1,8 @@
-use crate::commands::Command;
+use crate::commands::Statement;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DummyProcedure {
     pub args: Vec<String>,
-    pub commands: Vec<Command>,
+    pub commands: Vec<Statement>,
 }
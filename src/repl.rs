@@ -0,0 +1,104 @@
+use crate::session::Session;
+use crate::utils::ReplArgs;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::{Path, PathBuf};
+
+const HELP: &str = "\
+Type Logo commands to run them. A line ending in [ or starting with TO
+carries on until its ] or END.
+  :vars          list variables
+  :procs         list procedures
+  :turtle        show where the turtle is
+  :save out.svg  save the drawing as .svg or .png
+  :help          show this message
+  :quit          leave (or press Ctrl-D)";
+
+/// How many blocks a line opens (1) or closes (-1)
+fn block_change(line: &str) -> i32 {
+    let line = line.trim();
+    if line.ends_with('[') || line.split_whitespace().next() == Some("TO") {
+        1
+    } else if line == "]" || line == "END" {
+        -1
+    } else {
+        0
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".rslogo_history"))
+}
+
+/// Read lines from the terminal and run them until Ctrl-D or :quit
+pub fn run_repl(args: ReplArgs) -> Result<(), String> {
+    let background = *unsvg::COLORS
+        .get(args.background)
+        .ok_or_else(|| "Background colour must be between 0 and 15".to_string())?;
+    let mut session = Session::new(args.width, args.height, background);
+    let mut editor = DefaultEditor::new().map_err(|e| e.to_string())?;
+    if let Some(path) = history_path() {
+        // there is no history the first time
+        let _ = editor.load_history(&path);
+    }
+    println!("rslogo repl, :help for help");
+
+    let mut block: Vec<String> = Vec::new();
+    let mut depth = 0;
+    loop {
+        let prompt = if block.is_empty() { "? " } else { "> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // Ctrl-C throws away a half typed block
+                block.clear();
+                depth = 0;
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.to_string()),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        // meta-commands start with a colon, which Logo lines never do
+        if block.is_empty() && line.trim_start().starts_with(':') {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(":quit"), _) | (Some(":q"), _) => break,
+                (Some(":help"), _) => println!("{}", HELP),
+                (Some(":vars"), _) => print!("{}", session.vars()),
+                (Some(":procs"), _) => print!("{}", session.procs()),
+                (Some(":turtle"), _) => print!("{}", session.queries.describe()),
+                (Some(":save"), Some(path)) => match session.save(Path::new(path)) {
+                    Ok(()) => println!("saved {}", path),
+                    Err(e) => eprintln!("\x1b[31mError saving image: {}\x1b[0m", e),
+                },
+                (Some(":save"), None) => eprintln!("usage: :save out.svg"),
+                (name, _) => eprintln!(
+                    "unknown command {}, :help for help",
+                    name.unwrap_or_default()
+                ),
+            }
+            continue;
+        }
+
+        depth += block_change(&line);
+        block.push(line);
+        if depth > 0 {
+            continue;
+        }
+        let lines: Vec<&str> = block.iter().map(|line| line.as_str()).collect();
+        if let Err(e) = session.run(&lines) {
+            eprintln!("\x1b[31mError processing commands: {}\x1b[0m", e);
+        }
+        block.clear();
+        depth = 0;
+    }
+
+    if let Some(path) = history_path() {
+        let _ = editor.save_history(&path);
+    }
+    Ok(())
+}
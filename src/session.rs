@@ -0,0 +1,102 @@
+use crate::backend::{RecordingBackend, RenderBackend};
+use crate::canvas::{Canvas, RasterOptions};
+use crate::executer::{draw_turtle, execute_statement, NoObserver, Observer};
+use crate::procedures::DummyProcedure;
+use crate::utils::{extract_commands, QueriesStruct};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Everything a program has done so far, kept between runs
+pub struct Session {
+    pub variable_table: HashMap<String, String>,
+    pub dummy_procedures: HashMap<String, DummyProcedure>,
+    pub queries: QueriesStruct,
+    pub image: RecordingBackend,
+}
+
+impl Session {
+    pub fn new(width: u32, height: u32, background: unsvg::Color) -> Session {
+        let mut image = RecordingBackend::new(width, height);
+        image.set_background(background);
+        Session {
+            variable_table: HashMap::new(),
+            dummy_procedures: HashMap::new(),
+            queries: QueriesStruct::new(width, height),
+            image,
+        }
+    }
+
+    /// Parse and run some lines of Logo. Commands before an error keep their effect.
+    pub fn run(&mut self, lines: &[&str]) -> Result<(), String> {
+        self.run_observed(lines, &mut NoObserver)
+    }
+
+    /// Like `run`, with an observer such as the debugger watching
+    pub fn run_observed(
+        &mut self,
+        lines: &[&str],
+        observer: &mut dyn Observer,
+    ) -> Result<(), String> {
+        let lines: Vec<&str> = lines.iter().map(|line| line.trim()).collect();
+        let (commands, _) = extract_commands(&lines, 0, &mut self.dummy_procedures)?;
+        let mut map: HashMap<String, String> = HashMap::new();
+        for statement in commands.iter() {
+            execute_statement(
+                statement,
+                &mut self.variable_table,
+                &mut self.dummy_procedures,
+                false,
+                &mut map,
+                &mut self.queries,
+                &mut self.image,
+                observer,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Every variable and its value, sorted by name
+    pub fn vars(&self) -> String {
+        describe_variables(&self.variable_table)
+    }
+
+    /// Every procedure with its parameters, sorted by name
+    pub fn procs(&self) -> String {
+        let mut names: Vec<&String> = self.dummy_procedures.keys().collect();
+        names.sort();
+        names
+            .iter()
+            .map(|name| {
+                let procedure = &self.dummy_procedures[*name];
+                let mut line = format!("TO {}", name);
+                for arg in procedure.args.iter() {
+                    line.push_str(&format!(" {}", arg));
+                }
+                format!("{} ({} commands)\n", line, procedure.commands.len())
+            })
+            .collect()
+    }
+
+    /// Save the drawing so far, including the turtle if it is shown
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let (width, height) = self.image.get_dimensions();
+        let mut canvas = Canvas::new(width, height);
+        self.image.replay(&mut canvas);
+        draw_turtle(&self.queries, &mut canvas)?;
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("svg") => canvas.save_svg(path, 2),
+            Some("png") => canvas.save_png(path, RasterOptions::default()),
+            _ => Err("File extension not supported".to_string()),
+        }
+    }
+}
+
+/// One `name = value` line per variable, sorted by name
+pub fn describe_variables(table: &HashMap<String, String>) -> String {
+    let mut names: Vec<&String> = table.keys().collect();
+    names.sort();
+    names
+        .iter()
+        .map(|name| format!("{} = {}\n", name, table[*name]))
+        .collect()
+}
@@ -0,0 +1,267 @@
+use crate::backend::point_at;
+use crate::canvas::{escape_xml, hex_color, opacity_attribute, stroke_attributes, Canvas, Element};
+
+/// One element inside a style group, e.g. `<path d="..."/>`
+struct Child {
+    tag: &'static str,
+    /// For paths, only the path data
+    attributes: String,
+    content: Option<String>,
+}
+
+/// Consecutive elements drawn with the same style, written as one `<g>`
+struct Group {
+    style: String,
+    children: Vec<Child>,
+    /// Where the last path child ends, if more lines can be added to it
+    pen: Option<(String, String)>,
+    /// Whether the last path command was a line, so more points can follow it
+    after_line: bool,
+}
+
+/// A number rounded to `precision` decimal places, without trailing zeros
+pub fn format_number(value: f32, precision: usize) -> String {
+    let number = format!("{:.*}", precision, value);
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &number
+    };
+    match number {
+        "-0" => "0".to_string(),
+        number => number.to_string(),
+    }
+}
+
+/// Write a small svg of the canvas. Touching lines and arcs of the same style
+/// are joined into a single path, elements drawn one after another with the
+/// same style share a group, and coordinates are rounded to `precision`
+/// decimal places. Drawing order is kept, so the image looks the same.
+pub fn to_compact_svg(canvas: &Canvas, precision: usize) -> String {
+    let round = |point: (f32, f32)| {
+        (
+            format_number(point.0, precision),
+            format_number(point.1, precision),
+        )
+    };
+    let mut svg = canvas.svg_header();
+    let mut group: Option<Group> = None;
+
+    for element in canvas.elements().iter() {
+        let style = match element {
+            Element::Line(line) => format!("fill=\"none\" {}", stroke_attributes(&line.style)),
+            Element::Arc(arc) => format!("fill=\"none\" {}", stroke_attributes(&arc.style)),
+            Element::Polygon(polygon) => format!(
+                "fill=\"{}\"{}",
+                hex_color(polygon.style.color),
+                opacity_attribute("fill-opacity", polygon.style.fill_alpha)
+            ),
+            Element::Label(label) => format!(
+                "font-family=\"sans-serif\" font-size=\"14\" fill=\"{}\"{}",
+                hex_color(label.style.color),
+                opacity_attribute("fill-opacity", label.style.pen_alpha)
+            ),
+        };
+        if group.as_ref().map(|group| &group.style) != Some(&style) {
+            if let Some(finished) = group.take() {
+                svg.push_str(&group_svg(finished));
+            }
+        }
+        let group = group.get_or_insert_with(|| Group {
+            style,
+            children: Vec::new(),
+            pen: None,
+            after_line: false,
+        });
+
+        match element {
+            Element::Line(line) => {
+                let (start, end) = (round(line.start), round(line.end));
+                // a line straight after another only needs its end point
+                let after_line = group.after_line && group.pen.as_ref() == Some(&start);
+                let d = path_data(group, start);
+                if after_line {
+                    d.push_str(&format!(" {} {}", end.0, end.1));
+                } else {
+                    d.push_str(&format!("L{} {}", end.0, end.1));
+                }
+                group.after_line = true;
+                group.pen = Some(end);
+                end_stroke(group, line.style.pen_alpha);
+            }
+            Element::Arc(arc) => {
+                let start = round(point_at(arc.center, arc.start, arc.radius));
+                let radius = format_number(arc.radius, precision);
+                let pieces = (arc.sweep.abs() / 180.0).ceil().max(1.0);
+                let sweep_flag = if arc.sweep > 0.0 { 1 } else { 0 };
+                let mut end = start.clone();
+                let d = path_data(group, start);
+                for piece in 1..=(pieces as usize) {
+                    let angle = arc.start + arc.sweep * piece as f32 / pieces;
+                    end = round(point_at(arc.center, angle, arc.radius));
+                    d.push_str(&format!(
+                        "A{} {} 0 0 {} {} {}",
+                        radius, radius, sweep_flag, end.0, end.1
+                    ));
+                }
+                group.after_line = false;
+                group.pen = Some(end);
+                end_stroke(group, arc.style.pen_alpha);
+            }
+            Element::Polygon(polygon) => {
+                let points: Vec<String> = polygon
+                    .points
+                    .iter()
+                    .map(|point| {
+                        let (x, y) = round(*point);
+                        format!("{},{}", x, y)
+                    })
+                    .collect();
+                group.children.push(Child {
+                    tag: "polygon",
+                    attributes: format!("points=\"{}\"", points.join(" ")),
+                    content: None,
+                });
+            }
+            Element::Label(label) => {
+                let (x, y) = round(label.point);
+                group.children.push(Child {
+                    tag: "text",
+                    attributes: format!("x=\"{}\" y=\"{}\"", x, y),
+                    content: Some(escape_xml(&label.text)),
+                });
+            }
+        }
+    }
+    if let Some(finished) = group.take() {
+        svg.push_str(&group_svg(finished));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The path data to add a stroke starting at `start` to: the current path
+/// if it ends there, otherwise a new subpath (or path) moved to `start`
+fn path_data(group: &mut Group, start: (String, String)) -> &mut String {
+    let joined = group.pen.as_ref() == Some(&start);
+    if group.pen.is_none() {
+        group.children.push(Child {
+            tag: "path",
+            attributes: String::new(),
+            content: None,
+        });
+    }
+    let child = group.children.last_mut().expect("a path was just added");
+    if !joined {
+        child
+            .attributes
+            .push_str(&format!("M{} {}", start.0, start.1));
+    }
+    &mut child.attributes
+}
+
+/// Overlapping parts of one path are only painted once, so see-through
+/// strokes each need their own path to look the same as separate lines
+fn end_stroke(group: &mut Group, alpha: f32) {
+    if alpha < 1.0 {
+        group.pen = None;
+    }
+}
+
+fn child_svg(child: &Child, style: &str) -> String {
+    let attributes = match child.tag {
+        "path" => format!("d=\"{}\"", child.attributes),
+        _ => child.attributes.clone(),
+    };
+    let attributes = match style {
+        "" => attributes,
+        style => format!("{} {}", attributes, style),
+    };
+    match &child.content {
+        Some(content) => format!("<{} {}>{}</{}>", child.tag, attributes, content, child.tag),
+        None => format!("<{} {}/>", child.tag, attributes),
+    }
+}
+
+/// A lone element carries its own style, anything more shares a `<g>`
+fn group_svg(group: Group) -> String {
+    if group.children.len() == 1 {
+        return format!("  {}\n", child_svg(&group.children[0], &group.style));
+    }
+    let mut svg = format!("  <g {}>\n", group.style);
+    for child in group.children.iter() {
+        svg.push_str(&format!("    {}\n", child_svg(child, "")));
+    }
+    svg.push_str("  </g>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{RenderBackend, Style};
+
+    fn pen(color: usize, pen_alpha: f32) -> Style {
+        Style {
+            color: unsvg::COLORS[color],
+            pen_alpha,
+            ..Style::default()
+        }
+    }
+
+    /// The compact svg without its header and closing tag
+    fn body(canvas: &Canvas, precision: usize) -> String {
+        let svg = to_compact_svg(canvas, precision);
+        let body = svg.strip_prefix(&canvas.svg_header()).unwrap();
+        body.strip_suffix("</svg>\n").unwrap().to_string()
+    }
+
+    #[test]
+    fn touching_lines_become_one_path() {
+        let mut canvas = Canvas::new(100, 100);
+        canvas.set_style(pen(7, 1.0));
+        canvas.move_to((0.0, 0.0));
+        canvas.line_to((10.0, 0.0));
+        canvas.line_to((10.0, 10.0));
+        canvas.line_to((0.0, 10.0));
+        // a gap starts a new subpath of the same path
+        canvas.move_to((50.0, 50.0));
+        canvas.line_to((60.0, 50.0));
+        assert_eq!(
+            body(&canvas, 2),
+            "  <path d=\"M0 0L10 0 10 10 0 10M50 50L60 50\" fill=\"none\" stroke=\"#ffffff\"/>\n"
+        );
+    }
+
+    #[test]
+    fn elements_of_one_style_share_a_group() {
+        let mut canvas = Canvas::new(100, 100);
+        canvas.set_style(pen(4, 1.0));
+        canvas.move_to((0.0, 0.0));
+        canvas.line_to((10.0, 0.0));
+        canvas.text((5.0, 5.0), "x");
+        canvas.set_style(pen(7, 0.5));
+        canvas.move_to((0.0, 20.0));
+        canvas.line_to((10.0, 20.0));
+        // see-through lines are kept apart, so where they cross is painted twice
+        canvas.line_to((10.0, 30.0));
+        let expected = concat!(
+            "  <path d=\"M0 0L10 0\" fill=\"none\" stroke=\"#ff0000\"/>\n",
+            "  <text x=\"5\" y=\"5\" font-family=\"sans-serif\" font-size=\"14\" fill=\"#ff0000\">x</text>\n",
+            "  <g fill=\"none\" stroke=\"#ffffff\" stroke-opacity=\"0.5\">\n",
+            "    <path d=\"M0 20L10 20\"/>\n",
+            "    <path d=\"M10 20L10 30\"/>\n",
+            "  </g>\n",
+        );
+        assert_eq!(body(&canvas, 2), expected);
+    }
+
+    #[test]
+    fn numbers_are_rounded_without_trailing_zeros() {
+        assert_eq!(format_number(1.256, 2), "1.26");
+        assert_eq!(format_number(1.5, 2), "1.5");
+        assert_eq!(format_number(2.0, 2), "2");
+        assert_eq!(format_number(-0.001, 2), "0");
+        assert_eq!(format_number(12.7, 0), "13");
+    }
+}
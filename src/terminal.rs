@@ -0,0 +1,176 @@
+use crate::canvas::{Canvas, RasterOptions};
+use clap::ValueEnum;
+
+/// Characters used to draw a terminal preview
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TerminalStyle {
+    /// Braille patterns, 2x4 dots per character
+    Braille,
+    /// Half blocks, 1x2 dots per character
+    Blocks,
+    /// Plain ASCII, 1x2 dots per character
+    Ascii,
+}
+
+/// How a drawing is printed to the terminal
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalOptions {
+    /// Width of the preview in characters
+    pub columns: usize,
+    pub style: TerminalStyle,
+    /// Colour the output with ANSI escape codes
+    pub color: bool,
+}
+
+/// How far a pixel has to be from the background to count as drawn on
+const INK_THRESHOLD: u32 = 48;
+
+/// A dot of the preview: the most strongly drawn pixel under it, if any
+type Dot = Option<((u8, u8, u8), u32)>;
+
+/// Rasterise the canvas and draw it with text characters.
+/// Dots are square, as terminal characters are about twice as high as wide.
+pub fn to_terminal(canvas: &Canvas, options: TerminalOptions) -> Result<String, String> {
+    let pixmap = canvas.render_pixmap(RasterOptions::default())?;
+    let background = canvas.background();
+    let background = (background.red, background.green, background.blue);
+
+    let (dots_per_column, dots_per_row) = match options.style {
+        TerminalStyle::Braille => (2, 4),
+        TerminalStyle::Blocks | TerminalStyle::Ascii => (1, 2),
+    };
+    let columns = options.columns.max(1);
+    let dots_x = columns * dots_per_column;
+    let pixels_per_dot = pixmap.width() as f32 / dots_x as f32;
+    let dots_y = (pixmap.height() as f32 / pixels_per_dot).round().max(1.0) as usize;
+    let rows = dots_y.div_ceil(dots_per_row);
+
+    let dot = |x: usize, y: usize| -> Dot {
+        if y >= dots_y {
+            return None;
+        }
+        let range = |index: usize, size: u32| {
+            let start = ((index as f32 * pixels_per_dot) as u32).min(size - 1);
+            let end = (((index + 1) as f32 * pixels_per_dot) as u32).clamp(start + 1, size);
+            start..end
+        };
+        let mut strongest: Dot = None;
+        for pixel_y in range(y, pixmap.height()) {
+            for pixel_x in range(x, pixmap.width()) {
+                let color = match pixmap.pixel(pixel_x, pixel_y) {
+                    Some(pixel) => pixel.demultiply(),
+                    None => continue,
+                };
+                let color = (color.red(), color.green(), color.blue());
+                let distance = color_distance(color, background);
+                if distance >= INK_THRESHOLD && strongest.is_none_or(|(_, best)| distance > best) {
+                    strongest = Some((color, distance));
+                }
+            }
+        }
+        strongest
+    };
+
+    let mut output = String::new();
+    for row in 0..rows {
+        let mut current: Option<String> = None;
+        for column in 0..columns {
+            let (character, escape) = match options.style {
+                TerminalStyle::Braille => {
+                    // braille dots are numbered down the left column, then the right,
+                    // with the bottom row added later as bits 7 and 8
+                    const BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                    let mut bits = 0;
+                    let mut strongest: Dot = None;
+                    for (dx, column_bits) in BITS.iter().enumerate() {
+                        for (dy, bit) in column_bits.iter().enumerate() {
+                            let found = dot(column * 2 + dx, row * 4 + dy);
+                            if let Some((_, distance)) = found {
+                                bits |= bit;
+                                if strongest.is_none_or(|(_, best)| distance > best) {
+                                    strongest = found;
+                                }
+                            }
+                        }
+                    }
+                    // an empty cell is a plain space, which reads better in logs
+                    let character = match bits {
+                        0 => ' ',
+                        _ => char::from_u32(0x2800 + bits).unwrap_or(' '),
+                    };
+                    (character, strongest.map(|(color, _)| foreground(color)))
+                }
+                TerminalStyle::Blocks => {
+                    let top = dot(column, row * 2);
+                    let bottom = dot(column, row * 2 + 1);
+                    if options.color {
+                        // every cell is a top half block, with the bottom half as its background
+                        let top = top.map_or(background, |(color, _)| color);
+                        let bottom = bottom.map_or(background, |(color, _)| color);
+                        ('▀', Some(format!("{}{}", foreground(top), background_escape(bottom))))
+                    } else {
+                        let character = match (top.is_some(), bottom.is_some()) {
+                            (false, false) => ' ',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (true, true) => '█',
+                        };
+                        (character, None)
+                    }
+                }
+                TerminalStyle::Ascii => {
+                    let top = dot(column, row * 2);
+                    let bottom = dot(column, row * 2 + 1);
+                    let character = match (top.is_some(), bottom.is_some()) {
+                        (false, false) => ' ',
+                        (true, false) => '\'',
+                        (false, true) => '.',
+                        (true, true) => ':',
+                    };
+                    let strongest = match (top, bottom) {
+                        (Some(top), Some(bottom)) => Some(if bottom.1 > top.1 { bottom } else { top }),
+                        (top, bottom) => top.or(bottom),
+                    };
+                    (character, strongest.map(|(color, _)| foreground(color)))
+                }
+            };
+
+            // only write an escape code when the colour changes
+            if options.color && escape.is_some() && escape != current {
+                output.push_str(escape.as_deref().unwrap_or_default());
+                current = escape;
+            }
+            output.push(character);
+        }
+        if options.color && current.is_some() {
+            output.push_str("\x1b[0m");
+        }
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+fn color_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    (a.0 as i32 - b.0 as i32).unsigned_abs()
+        + (a.1 as i32 - b.1 as i32).unsigned_abs()
+        + (a.2 as i32 - b.2 as i32).unsigned_abs()
+}
+
+/// The nearest colour in the 6x6x6 cube of the 256 colour ANSI palette
+fn ansi_color((red, green, blue): (u8, u8, u8)) -> u8 {
+    // the cube's levels are 0, 95, 135, 175, 215 and 255
+    let level = |value: u8| match value {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (value - 35) / 40,
+    };
+    16 + 36 * level(red) + 6 * level(green) + level(blue)
+}
+
+fn foreground(color: (u8, u8, u8)) -> String {
+    format!("\x1b[38;5;{}m", ansi_color(color))
+}
+
+fn background_escape(color: (u8, u8, u8)) -> String {
+    format!("\x1b[48;5;{}m", ansi_color(color))
+}
@@ -0,0 +1,163 @@
+use crate::commands::{Command, Statement};
+use crate::executer::{Observer, Scope};
+use crate::operations::Operation;
+use crate::parser::parse_operation;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+
+/// How each line of a trace is written
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TraceFormat {
+    /// `12: FORWARD "50 -> x 200, y 150, heading 0`
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// One executed command
+#[derive(Debug, Serialize)]
+pub struct TraceRecord {
+    pub line: usize,
+    pub command: String,
+    pub args: Vec<String>,
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+    /// Why the command failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Writes a line for every command the program runs.
+/// Simple commands are written once they have run, showing where the
+/// turtle ended up. IF, WHILE and procedure calls are written before
+/// their blocks run, so the trace reads in the order of the program.
+/// The statement that fails is written with its error.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    /// Arguments of the command being run, evaluated before it ran
+    pending: Vec<String>,
+    /// The failing statement has been written, so the procedure
+    /// calls the error passes back through are not
+    failure_written: bool,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer {
+            out,
+            format,
+            pending: Vec::new(),
+            failure_written: false,
+        }
+    }
+
+    fn write(
+        &mut self,
+        statement: &Statement,
+        args: Vec<String>,
+        scope: &Scope,
+        error: Option<&str>,
+    ) -> Result<(), String> {
+        let number = |value: &str| value.trim_start_matches('"').parse::<f32>().unwrap_or(0.0);
+        let record = TraceRecord {
+            line: statement.line,
+            command: statement.command.name().to_string(),
+            args,
+            x: number(&scope.queries.xcor),
+            y: number(&scope.queries.ycor),
+            heading: number(&scope.queries.heading),
+            error: error.map(|error| error.to_string()),
+        };
+        let line = match self.format {
+            TraceFormat::Text => {
+                let mut line = format!("{}: {}", record.line, record.command);
+                for arg in record.args.iter() {
+                    line.push_str(&format!(" {}", arg));
+                }
+                match &record.error {
+                    Some(error) => format!("{} -> error: {}", line, error),
+                    None => format!(
+                        "{} -> x {}, y {}, heading {}",
+                        line, record.x, record.y, record.heading
+                    ),
+                }
+            }
+            TraceFormat::Json => serde_json::to_string(&record).map_err(|e| e.to_string())?,
+        };
+        self.out
+            .write_all(format!("{}\n", line).as_bytes())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether the command runs other statements, and so is traced before it runs
+fn has_block(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::If(_, _) | Command::Whlie(_, _) | Command::Procedure(_, _)
+    )
+}
+
+/// The command's arguments as the program is about to see them
+fn evaluate_args(command: &Command, scope: &Scope) -> Vec<String> {
+    let table = scope.procedure_args.unwrap_or(scope.variable_table);
+    let evaluate = |operation: &Operation| {
+        parse_operation(operation, table, scope.queries).unwrap_or_else(|_| "?".to_string())
+    };
+    let lookup = |word: &String| {
+        if word.starts_with(':') {
+            table.get(word).cloned().unwrap_or_else(|| "?".to_string())
+        } else {
+            word.clone()
+        }
+    };
+    match command {
+        Command::Make(name, value) => vec![name.clone(), evaluate(value)],
+        // like the executer, ADDASSIGN only sees variables made outside procedures
+        Command::Addassign(name, value) => vec![
+            name.clone(),
+            parse_operation(value, scope.variable_table, scope.queries)
+                .unwrap_or_else(|_| "?".to_string()),
+        ],
+        Command::Procedure(_, params) => params.iter().map(lookup).collect(),
+        command => command.operations().into_iter().map(evaluate).collect(),
+    }
+}
+
+impl Observer for Tracer {
+    fn before(&mut self, statement: &Statement, scope: &Scope) -> Result<(), String> {
+        self.failure_written = false;
+        let args = evaluate_args(&statement.command, scope);
+        if has_block(&statement.command) {
+            self.write(statement, args, scope, None)
+        } else {
+            self.pending = args;
+            Ok(())
+        }
+    }
+
+    fn after(&mut self, statement: &Statement, scope: &Scope) -> Result<(), String> {
+        if has_block(&statement.command) {
+            return Ok(());
+        }
+        let args = std::mem::take(&mut self.pending);
+        self.write(statement, args, scope, None)
+    }
+
+    fn failed(&mut self, statement: &Statement, scope: &Scope, error: &str) {
+        if self.failure_written {
+            return;
+        }
+        self.failure_written = true;
+        let args = if has_block(&statement.command) {
+            evaluate_args(&statement.command, scope)
+        } else {
+            std::mem::take(&mut self.pending)
+        };
+        // the program is stopping with this error anyway
+        let _ = self.write(statement, args, scope, Some(error));
+    }
+}
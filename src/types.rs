@@ -0,0 +1,493 @@
+use crate::commands::{Command, Statement};
+use crate::operations::Operation;
+use crate::procedures::DummyProcedure;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Words that give a number without a " or :
+const QUERIES: [&str; 4] = ["XCOR", "YCOR", "HEADING", "COLOR"];
+
+/// The type of a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Number,
+    Boolean,
+    /// A word with no " or : in front, which is not a value
+    Word,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "a number"),
+            Type::Boolean => write!(f, "TRUE or FALSE"),
+            Type::Word => write!(f, "a word"),
+        }
+    }
+}
+
+/// A value of the wrong type, found on `line` at the first `token` on it
+#[derive(Debug, Clone)]
+pub struct TypeError {
+    pub line: usize,
+    pub token: String,
+    pub message: String,
+    /// Whether running the program is sure to fail here: the value is written
+    /// out rather than read from a variable, and the line is always run
+    pub certain: bool,
+}
+
+/// The variables of one scope, the main program or a procedure,
+/// and the type variable each has
+type Scope = HashMap<String, usize>;
+
+/// Works out the type of every value from how it is used.
+///
+/// Each variable gets a type variable, which is set the first time the
+/// variable is used somewhere that needs a type. Two variables become the
+/// same type variable when one is given the other's value. Procedure bodies
+/// are checked before their calls, so calls can be checked against what
+/// the body needs from each parameter.
+#[derive(Default)]
+pub struct Inference {
+    /// Union-find over type variables
+    parent: Vec<usize>,
+    bound: Vec<Option<Type>>,
+    globals: Scope,
+    /// The variables of each procedure, including its parameters
+    locals: HashMap<String, Scope>,
+    /// Each procedure's parameters, as `:name`
+    params: HashMap<String, Vec<String>>,
+    /// Types passed to parameters the body does not decide, from the first call
+    call_types: HashMap<(String, String), Type>,
+    /// Whether the statements being checked might not run,
+    /// inside a procedure or an IF or WHILE block
+    conditional: bool,
+    pub errors: Vec<TypeError>,
+}
+
+impl Inference {
+    pub fn new() -> Inference {
+        Inference::default()
+    }
+
+    fn fresh(&mut self, bound: Option<Type>) -> usize {
+        self.parent.push(self.parent.len());
+        self.bound.push(bound);
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, var: usize) -> usize {
+        let mut root = var;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        self.parent[var] = root;
+        root
+    }
+
+    fn resolve(&mut self, var: usize) -> Option<Type> {
+        let root = self.find(var);
+        self.bound[root]
+    }
+
+    /// Make `var` have type `expected`, or give the type it already has
+    fn require(&mut self, var: usize, expected: Type) -> Result<(), Type> {
+        let root = self.find(var);
+        match self.bound[root] {
+            None => {
+                self.bound[root] = Some(expected);
+                Ok(())
+            }
+            Some(found) if found == expected => Ok(()),
+            Some(found) => Err(found),
+        }
+    }
+
+    /// Make two type variables the same, or give the two types that differ
+    fn unify(&mut self, a: usize, b: usize) -> Result<(), (Type, Type)> {
+        let (a, b) = (self.find(a), self.find(b));
+        match (self.bound[a], self.bound[b]) {
+            (Some(left), Some(right)) if left != right => Err((left, right)),
+            (left, right) => {
+                self.parent[b] = a;
+                self.bound[a] = left.or(right);
+                Ok(())
+            }
+        }
+    }
+
+    fn variable(&mut self, scope: &mut Scope, name: &str) -> usize {
+        match scope.get(name) {
+            Some(var) => *var,
+            None => {
+                let var = self.fresh(None);
+                scope.insert(name.to_string(), var);
+                var
+            }
+        }
+    }
+
+    /// Report a type error. `fixed` says whether the wrong type was written
+    /// out, so it does not depend on what a variable holds.
+    fn error(&mut self, line: usize, token: &str, message: String, fixed: bool) {
+        self.errors.push(TypeError {
+            line,
+            token: token.to_string(),
+            message,
+            certain: fixed && !self.conditional,
+        });
+    }
+
+    fn is_variable(operation: &Operation) -> bool {
+        matches!(operation, Operation::Base(word) if word.starts_with(':'))
+    }
+
+    /// What was found instead of `expected`, naming the variable or word if there is one
+    fn found(operation: &Operation, found: Type) -> String {
+        match operation {
+            Operation::Base(word) if word.starts_with(':') => format!("but {} is {}", word, found),
+            Operation::Base(word) if found == Type::Word => format!(
+                "found the word {} (numbers start with \" and variables with :)",
+                word
+            ),
+            _ => format!("found {}", found),
+        }
+    }
+
+    /// Check that `operation`, with type variable `var`, is of type `expected`
+    fn expect(
+        &mut self,
+        line: usize,
+        token: &str,
+        operation: &Operation,
+        var: usize,
+        expected: Type,
+    ) {
+        if let Err(found) = self.require(var, expected) {
+            let message = format!(
+                "{} needs {}, {}",
+                token,
+                expected,
+                Inference::found(operation, found)
+            );
+            let fixed = !Inference::is_variable(operation);
+            self.error(line, token, message, fixed);
+        }
+    }
+
+    /// The type variable of an operation's value
+    fn operation(&mut self, line: usize, operation: &Operation, scope: &mut Scope) -> usize {
+        let (name, a, b) = match operation.operator() {
+            Some(operator) => operator,
+            None => {
+                let word = match operation {
+                    Operation::Base(word) => word.as_str(),
+                    _ => unreachable!(), // only a Base has no operator
+                };
+                return match word.strip_prefix('"') {
+                    Some("TRUE") | Some("FALSE") => self.fresh(Some(Type::Boolean)),
+                    Some(_) => self.fresh(Some(Type::Number)),
+                    None if word.starts_with(':') => self.variable(scope, word),
+                    None if QUERIES.contains(&word) => self.fresh(Some(Type::Number)),
+                    None => self.fresh(Some(Type::Word)),
+                };
+            }
+        };
+        let left = self.operation(line, a, scope);
+        let right = self.operation(line, b, scope);
+        let (operands, result) = match name {
+            "+" | "-" | "*" | "/" => (Some(Type::Number), Type::Number),
+            "GT" | "LT" => (Some(Type::Number), Type::Boolean),
+            "AND" | "OR" => (Some(Type::Boolean), Type::Boolean),
+            _ => (None, Type::Boolean),
+        };
+        match operands {
+            Some(expected) => {
+                self.expect(line, name, a, left, expected);
+                self.expect(line, name, b, right, expected);
+            }
+            None => {
+                // EQ and NE compare two numbers or two booleans
+                let word = [(a, left), (b, right)]
+                    .into_iter()
+                    .find(|(_, var)| self.resolve(*var) == Some(Type::Word));
+                if let Some((operation, _)) = word {
+                    let message = format!(
+                        "{} needs numbers or TRUE or FALSE, {}",
+                        name,
+                        Inference::found(operation, Type::Word)
+                    );
+                    self.error(line, name, message, true);
+                } else if let Err((left, right)) = self.unify(left, right) {
+                    let fixed = !Inference::is_variable(a) && !Inference::is_variable(b);
+                    self.error(
+                        line,
+                        name,
+                        format!("{} compares {} with {}", name, left, right),
+                        fixed,
+                    );
+                }
+            }
+        }
+        self.fresh(Some(result))
+    }
+
+    /// The type variable of a value a command is given, which must not be a word.
+    /// `expected` is the type the command needs, if it needs one in particular.
+    fn value(
+        &mut self,
+        line: usize,
+        keyword: &str,
+        operation: &Operation,
+        scope: &mut Scope,
+        expected: Option<Type>,
+    ) -> usize {
+        let var = self.operation(line, operation, scope);
+        if self.resolve(var) == Some(Type::Word) {
+            let expected = match expected {
+                Some(expected) => expected.to_string(),
+                None => format!("{} or {}", Type::Number, Type::Boolean),
+            };
+            let message = format!(
+                "{} needs {}, {}",
+                keyword,
+                expected,
+                Inference::found(operation, Type::Word)
+            );
+            let token = match operation {
+                Operation::Base(word) => word.as_str(),
+                _ => keyword,
+            };
+            self.error(line, token, message, true);
+            // already reported, so nothing more is expected of it
+            return self.fresh(None);
+        }
+        var
+    }
+
+    /// Infer types through statements run in `scope`.
+    /// `in_procedure` says whether ADDASSIGN has to reach out to the global variables.
+    fn statements(&mut self, statements: &[Statement], scope: &mut Scope, in_procedure: bool) {
+        for statement in statements.iter() {
+            let line = statement.line;
+            let keyword = statement.command.name().to_string();
+            match &statement.command {
+                Command::Make(name, value) => {
+                    let var = self.value(line, "MAKE", value, scope, None);
+                    let key = name.replace('"', ":");
+                    let variable = self.variable(scope, &key);
+                    if let Err((existing, found)) = self.unify(variable, var) {
+                        let message = format!(
+                            "MAKE gives {} {}, but elsewhere it is {}",
+                            key, found, existing
+                        );
+                        self.error(line, name, message, false);
+                    }
+                }
+                Command::Addassign(name, value) => {
+                    // ADDASSIGN always works on variables made outside procedures
+                    let mut globals = std::mem::take(&mut self.globals);
+                    let target = match in_procedure {
+                        true => &mut globals,
+                        false => &mut *scope,
+                    };
+                    let key = name.replace('"', ":");
+                    let variable = self.variable(target, &key);
+                    let var = self.operation(line, value, target);
+                    self.globals = globals;
+                    if let Err(found) = self.require(variable, Type::Number) {
+                        let message = format!("ADDASSIGN needs a number, but {} is {}", key, found);
+                        self.error(line, name, message, false);
+                    }
+                    self.expect(line, "ADDASSIGN", value, var, Type::Number);
+                }
+                Command::Procedure(name, args) => {
+                    let params = self.params.get(name).cloned().unwrap_or_default();
+                    for (param, arg) in params.iter().zip(args.iter()) {
+                        if !arg.starts_with(':') {
+                            continue;
+                        }
+                        let var = self.variable(scope, arg);
+                        let needed = self
+                            .locals
+                            .get(name)
+                            .and_then(|locals| locals.get(param))
+                            .copied();
+                        let needed = needed.and_then(|needed| self.resolve(needed));
+                        match needed {
+                            Some(expected) => {
+                                if let Err(found) = self.require(var, expected) {
+                                    let message = format!(
+                                        "{} needs {} for {}, but {} is {}",
+                                        name, expected, param, arg, found
+                                    );
+                                    self.error(line, arg, message, false);
+                                }
+                            }
+                            None => {
+                                if let Some(found) = self.resolve(var) {
+                                    self.call_types
+                                        .entry((name.clone(), param.clone()))
+                                        .or_insert(found);
+                                }
+                            }
+                        }
+                    }
+                }
+                Command::If(condition, block) | Command::Whlie(condition, block) => {
+                    let var = self.value(line, &keyword, condition, scope, Some(Type::Boolean));
+                    self.expect(line, &keyword, condition, var, Type::Boolean);
+                    let conditional = std::mem::replace(&mut self.conditional, true);
+                    self.statements(block, scope, in_procedure);
+                    self.conditional = conditional;
+                }
+                command => {
+                    for operation in command.operations() {
+                        let var = self.value(line, &keyword, operation, scope, Some(Type::Number));
+                        self.expect(line, &keyword, operation, var, Type::Number);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Infer the types in a procedure's body. Procedures should be
+    /// given in the order they are defined, before the main program.
+    pub fn procedure(&mut self, name: &str, args: &[String], commands: &[Statement]) {
+        let params: Vec<String> = args.iter().map(|arg| arg.replace('"', ":")).collect();
+        let mut scope = Scope::new();
+        for param in params.iter() {
+            self.variable(&mut scope, param);
+        }
+        // a procedure might never be called
+        let conditional = std::mem::replace(&mut self.conditional, true);
+        self.statements(commands, &mut scope, true);
+        self.conditional = conditional;
+        self.params.insert(name.to_string(), params);
+        self.locals.insert(name.to_string(), scope);
+    }
+
+    /// Infer the types in the main program, after its procedures
+    pub fn program(&mut self, statements: &[Statement]) {
+        let mut scope = std::mem::take(&mut self.globals);
+        self.statements(statements, &mut scope, false);
+        self.globals = scope;
+    }
+
+    /// The type of a variable in the main program, or inside a procedure
+    pub fn variable_type(&mut self, procedure: Option<&str>, name: &str) -> Option<Type> {
+        let var = match procedure {
+            Some(procedure) => self.locals.get(procedure)?.get(name).copied(),
+            None => self.globals.get(name).copied(),
+        }?;
+        self.resolve(var)
+    }
+
+    /// Each parameter of a procedure and its type, from the body or else from the calls to it
+    pub fn param_types(&mut self, procedure: &str) -> Vec<(String, Option<Type>)> {
+        let params = self.params.get(procedure).cloned().unwrap_or_default();
+        params
+            .into_iter()
+            .map(|param| {
+                let from_body = self.variable_type(Some(procedure), &param);
+                let from_calls = self
+                    .call_types
+                    .get(&(procedure.to_string(), param.clone()))
+                    .copied();
+                let found = from_body.or(from_calls);
+                (param, found)
+            })
+            .collect()
+    }
+}
+
+/// Infer the types of a parsed program and its procedures
+pub fn infer_program(
+    statements: &[Statement],
+    procedures: &HashMap<String, DummyProcedure>,
+) -> Inference {
+    // a procedure can only call ones defined before it, so check those first
+    let mut ordered: Vec<(&String, &DummyProcedure)> = procedures.iter().collect();
+    ordered.sort_by_key(|(_, procedure)| procedure.commands.first().map_or(0, |s| s.line));
+    let mut inference = Inference::new();
+    for (name, procedure) in ordered {
+        inference.procedure(name, &procedure.args, &procedure.commands);
+    }
+    inference.program(statements);
+    inference
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::extract_commands;
+
+    /// Parse a program and infer its types
+    fn infer(source: &str) -> Inference {
+        let lines: Vec<&str> = source.lines().map(|line| line.trim()).collect();
+        let mut procedures: HashMap<String, DummyProcedure> = HashMap::new();
+        let (statements, _) = extract_commands(&lines, 0, &mut procedures).unwrap();
+        infer_program(&statements, &procedures)
+    }
+
+    #[test]
+    fn a_correct_program_has_no_errors() {
+        let mut inference = infer(
+            "TO SIDE \"LENGTH\n  FORWARD :LENGTH\n  TURN \"90\nEND\nPENDOWN\nMAKE \"SIZE \"10\nMAKE \"DONE EQ :SIZE \"20\nWHILE NE :DONE \"TRUE [\n  SIDE :SIZE\n  ADDASSIGN \"SIZE \"5\n  MAKE \"DONE GT :SIZE \"20\n]",
+        );
+        assert!(inference.errors.is_empty());
+        assert_eq!(inference.variable_type(None, ":SIZE"), Some(Type::Number));
+        assert_eq!(inference.variable_type(None, ":DONE"), Some(Type::Boolean));
+        assert_eq!(
+            inference.variable_type(Some("SIDE"), ":LENGTH"),
+            Some(Type::Number)
+        );
+    }
+
+    #[test]
+    fn a_comparison_given_to_forward_is_certain() {
+        let inference = infer("FORWARD EQ \"1 \"2");
+        assert_eq!(inference.errors.len(), 1);
+        let error = &inference.errors[0];
+        assert_eq!(error.line, 1);
+        assert_eq!(error.message, "FORWARD needs a number, found TRUE or FALSE");
+        assert!(error.certain);
+    }
+
+    #[test]
+    fn a_word_names_the_type_needed() {
+        let inference = infer("IF HELLO [\n  FORWARD \"1\n]");
+        assert_eq!(
+            inference.errors[0].message,
+            "IF needs TRUE or FALSE, found the word HELLO (numbers start with \" and variables with :)"
+        );
+    }
+
+    #[test]
+    fn a_parameter_takes_its_type_from_a_call() {
+        let mut inference =
+            infer("TO KEEP \"VALUE\n  MAKE \"COPY :VALUE\nEND\nMAKE \"ON EQ \"1 \"1\nKEEP :ON");
+        assert!(inference.errors.is_empty());
+        assert_eq!(inference.variable_type(Some("KEEP"), ":VALUE"), None);
+        assert_eq!(
+            inference.param_types("KEEP"),
+            vec![(":VALUE".to_string(), Some(Type::Boolean))]
+        );
+    }
+
+    #[test]
+    fn a_variable_given_both_types_is_not_certain() {
+        let inference =
+            infer("MAKE \"x \"5\nFORWARD :x\nMAKE \"x EQ \"1 \"1\nIF :x [\n  FORWARD \"1\n]");
+        assert!(!inference.errors.is_empty());
+        assert!(inference.errors.iter().all(|error| !error.certain));
+    }
+
+    #[test]
+    fn a_branch_that_might_not_run_is_not_certain() {
+        let inference = infer("IF EQ \"1 \"2 [\n  FORWARD \"TRUE\n]");
+        assert_eq!(inference.errors.len(), 1);
+        assert!(!inference.errors[0].certain);
+    }
+}
@@ -1,24 +1,290 @@
 use crate::operations::Operation;
-use crate::commands::Command;
+use crate::commands::{Command, Statement};
 use crate::procedures::DummyProcedure;
 use crate::parser::parse_command;
-use clap::Parser;
+use crate::document::parse_page_length;
+use crate::limits::parse_duration;
+use crate::terminal::TerminalStyle;
+use crate::trace::TraceFormat;
+use crate::ast::AstFormat;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
 use std::collections::HashMap;
 
 /// A simple program to parse four arguments using clap.
 #[derive(Parser)]
+#[command(
+    override_usage = "rslogo [OPTIONS] <FILE_PATH> [IMAGE_PATH] <HEIGHT> <WIDTH>",
+    after_help = "Tools:\n  rslogo repl    Type in Logo and run it straight away\n  rslogo debug   Run a file one statement at a time\n  rslogo check   Find problems in a file without running it\n  rslogo fmt     Lay files out in the standard way\n  rslogo ast     Show how a file is parsed\n  rslogo lsp     Serve diagnostics, hover and more to editors\n  rslogo lint    Warn about code that probably does not do what was meant"
+)]
 pub struct Args {
     /// Path to a file
     pub file_path: std::path::PathBuf,
 
-    /// Path to an svg or png image
-    pub image_path: std::path::PathBuf,
+    /// Path to an svg or png image, which can be left out when --emit is given
+    pub image_path: Option<std::path::PathBuf>,
 
     /// Height
-    pub height: u32,
+    pub height: Option<u32>,
 
     /// Width
+    pub width: Option<u32>,
+
+    /// Background colour, as an index into the 16 Logo colours
+    #[arg(long, default_value_t = 0)]
+    pub background: usize,
+
+    /// Size of one pixel on paper in millimetres, for .hpgl and .gcode output
+    #[arg(long, default_value_t = 0.25)]
+    pub mm_per_pixel: f32,
+
+    /// Drawing speed in millimetres per minute, for .gcode output
+    #[arg(long, default_value_t = 1500.0)]
+    pub feed_rate: f32,
+
+    /// Page width for .pdf and .eps output, in points or with a mm suffix
+    /// (defaults to the image width in points)
+    #[arg(long, value_parser = parse_page_length)]
+    pub page_width: Option<f32>,
+
+    /// Page height for .pdf and .eps output, in points or with a mm suffix
+    /// (defaults to the image height in points)
+    #[arg(long, value_parser = parse_page_length)]
+    pub page_height: Option<f32>,
+
+    /// Scale the drawing to fill the .svg or .png image
+    #[arg(long, conflicts_with = "crop")]
+    pub fit: bool,
+
+    /// Shrink the .svg or .png image to the drawing
+    #[arg(long)]
+    pub crop: bool,
+
+    /// Space left around the drawing by --fit and --crop, in pixels
+    #[arg(long, default_value_t = 10.0)]
+    pub margin: f32,
+
+    /// Decimal places kept in the coordinates of .svg output
+    #[arg(long, default_value_t = 2)]
+    pub svg_precision: usize,
+
+    /// Pixels per unit of the drawing in .png and .gif output,
+    /// e.g. 2 for an image twice as wide and high
+    #[arg(long, default_value_t = 1.0)]
+    pub scale: f32,
+
+    /// Resolution of .png and .gif output, with 96 the same as --scale 1.
+    /// Also recorded in .png files, so they print at the right size.
+    #[arg(long, conflicts_with = "scale")]
+    pub dpi: Option<f32>,
+
+    /// Draw .png and .gif output with hard, pixelated edges
+    #[arg(long)]
+    pub no_antialias: bool,
+
+    /// Show the drawing being built: an animated .svg, a numbered
+    /// sequence of .png frames, or (always, for .gif) an animated gif
+    #[arg(long)]
+    pub animate: bool,
+
+    /// Drawing steps added in each frame of an animation
+    /// (defaults to enough for about 100 frames)
+    #[arg(long)]
+    pub steps_per_frame: Option<usize>,
+
+    /// Time each frame of an animation is shown for, in milliseconds
+    #[arg(long, default_value_t = 50)]
+    pub frame_delay: u16,
+
+    /// Pause before each statement and read debugger commands from the terminal
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Breakpoints for --debug: a line number or a procedure name.
+    /// Can be given more than once.
+    #[arg(long = "break", value_name = "LINE|PROCEDURE", requires = "debug")]
+    pub breakpoints: Vec<String>,
+
+    /// Stop the program after running this many statements
+    /// (every time round a WHILE loop counts too)
+    #[arg(long)]
+    pub max_steps: Option<u64>,
+
+    /// Stop the program when procedure calls nest deeper than this
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// Stop the program after this long, e.g. 10, 2.5s or 500ms
+    #[arg(long, value_parser = parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    /// Stop the program after drawing this many lines and arcs
+    #[arg(long)]
+    pub max_segments: Option<usize>,
+
+    /// Log every command run, with its line, arguments and where the turtle ends up
+    #[arg(long)]
+    pub trace: bool,
+
+    /// How --trace lines are written
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text, requires = "trace")]
+    pub trace_format: TraceFormat,
+
+    /// Write the --trace log to this file instead of stderr
+    #[arg(long, requires = "trace")]
+    pub trace_file: Option<std::path::PathBuf>,
+
+    /// Also print the drawing to stdout in this format
+    #[arg(long, value_enum)]
+    pub emit: Option<Emit>,
+
+    /// Width of the --emit terminal preview, in characters
+    #[arg(long, default_value_t = 80)]
+    pub terminal_width: usize,
+
+    /// Characters used by the --emit terminal preview
+    #[arg(long, value_enum, default_value_t = TerminalStyle::Braille)]
+    pub terminal_style: TerminalStyle,
+}
+
+impl Args {
+    /// Parse the command line. Clap fills positional arguments in order,
+    /// so when the image path is left out it has taken the height, and
+    /// everything is moved along one place here.
+    pub fn parse_command_line() -> Args {
+        let mut args = Args::parse();
+        if args.width.is_none() && args.emit.is_some() {
+            let height = args
+                .image_path
+                .take()
+                .and_then(|path| path.to_str()?.parse::<u32>().ok());
+            args.width = args.height.take();
+            args.height = height;
+        }
+        if args.height.is_none() || args.width.is_none() {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the height and width of the image are needed",
+                )
+                .exit();
+        }
+        args
+    }
+}
+
+/// Tools run as `rslogo <tool>` instead of running a file
+#[derive(Parser)]
+#[command(name = "rslogo")]
+pub enum Tool {
+    /// Type in Logo and run it straight away
+    Repl(ReplArgs),
+    /// Run a file one statement at a time
+    Debug(DebugArgs),
+    /// Find problems in a file without running it
+    Check(CheckArgs),
+    /// Lay files out in the standard way
+    Fmt(FmtArgs),
+    /// Show how a file is parsed
+    Ast(AstArgs),
+    /// Serve diagnostics, hover and more to editors over stdin and stdout
+    Lsp,
+    /// Warn about code that probably does not do what was meant
+    Lint(LintArgs),
+}
+
+#[derive(clap::Args)]
+pub struct ReplArgs {
+    /// Height of the image saved with :save
+    #[arg(long, default_value_t = 400)]
+    pub height: u32,
+
+    /// Width of the image saved with :save
+    #[arg(long, default_value_t = 400)]
     pub width: u32,
+
+    /// Background colour, as an index into the 16 Logo colours
+    #[arg(long, default_value_t = 0)]
+    pub background: usize,
+}
+
+#[derive(clap::Args)]
+pub struct DebugArgs {
+    /// Path to a file
+    pub file_path: std::path::PathBuf,
+
+    /// Pause at this line number, or when this procedure is called.
+    /// Can be given more than once.
+    #[arg(long = "break", value_name = "LINE|PROCEDURE")]
+    pub breakpoints: Vec<String>,
+
+    /// Height of the image the program draws on
+    #[arg(long, default_value_t = 400)]
+    pub height: u32,
+
+    /// Width of the image the program draws on
+    #[arg(long, default_value_t = 400)]
+    pub width: u32,
+}
+
+#[derive(clap::Args)]
+pub struct CheckArgs {
+    /// Path to a file
+    pub file_path: std::path::PathBuf,
+
+    /// Fail when there are warnings, not just errors
+    #[arg(long)]
+    pub deny_warnings: bool,
+}
+
+#[derive(clap::Args)]
+pub struct FmtArgs {
+    /// Files to format in place
+    #[arg(required = true)]
+    pub files: Vec<std::path::PathBuf>,
+
+    /// Only say which files are not formatted, failing if any are not
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(clap::Args)]
+pub struct AstArgs {
+    /// Path to a file
+    pub file_path: std::path::PathBuf,
+
+    /// Print an indented tree, or JSON
+    #[arg(long, value_enum, default_value_t = AstFormat::Tree)]
+    pub format: AstFormat,
+}
+
+#[derive(clap::Args)]
+pub struct LintArgs {
+    /// Path to a file
+    #[arg(required_unless_present = "list_rules")]
+    pub file_path: Option<std::path::PathBuf>,
+
+    /// Config file turning rules on and off, instead of the
+    /// nearest rslogo-lint.toml to the file
+    #[arg(long)]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Fail when there are warnings
+    #[arg(long)]
+    pub deny_warnings: bool,
+
+    /// Print the ID of each rule and what it looks for
+    #[arg(long)]
+    pub list_rules: bool,
+}
+
+/// Formats that can be printed with `--emit`
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Emit {
+    /// The display list of everything drawn, as JSON
+    Json,
+    /// A preview drawn with text characters, coloured unless NO_COLOR is set
+    Terminal,
 }
 
 #[derive(Debug)]
@@ -28,16 +294,106 @@ pub struct QueriesStruct {
     pub heading: String,
     pub color: String,
     pub is_pen_down: String,
+    pub pen_alpha: String,
+    pub fill_alpha: String,
+    pub is_turtle_shown: String,
+    pub turtle_stack: Vec<TurtleState>,
+    /// The directory of the program being run, which LSYSTEMFILE paths are
+    /// relative to. Empty, meaning the current directory, when there is no file.
+    pub source_dir: std::path::PathBuf,
+}
+
+/// Snapshot of the turtle saved by PUSHTURTLE
+/// and restored by POPTURTLE
+#[derive(Debug, Clone)]
+pub struct TurtleState {
+    pub xcor: String,
+    pub ycor: String,
+    pub heading: String,
+    pub color: String,
+    pub is_pen_down: String,
+    pub pen_alpha: String,
+    pub fill_alpha: String,
+}
+
+impl QueriesStruct {
+    /// A turtle in the middle of a `width` x `height` image,
+    /// facing up with the pen raised
+    pub fn new(width: u32, height: u32) -> QueriesStruct {
+        QueriesStruct {
+            xcor: format!("{}{}", "\"", (width / 2)),
+            ycor: format!("{}{}", "\"", (height / 2)),
+            heading: "\"0".to_string(),
+            color: "\"7".to_string(),
+            is_pen_down: "FALSE".to_string(),
+            pen_alpha: "\"1".to_string(),
+            fill_alpha: "\"1".to_string(),
+            is_turtle_shown: "FALSE".to_string(),
+            turtle_stack: Vec::new(),
+            source_dir: std::path::PathBuf::new(),
+        }
+    }
+
+    /// Save the current turtle onto the turtle stack
+    pub fn push_turtle(&mut self) {
+        self.turtle_stack.push(TurtleState {
+            xcor: self.xcor.clone(),
+            ycor: self.ycor.clone(),
+            heading: self.heading.clone(),
+            color: self.color.clone(),
+            is_pen_down: self.is_pen_down.clone(),
+            pen_alpha: self.pen_alpha.clone(),
+            fill_alpha: self.fill_alpha.clone(),
+        });
+    }
+
+    /// Position, heading and pen of the turtle, for people to read
+    pub fn describe(&self) -> String {
+        let number = |value: &str| value.trim_start_matches('"').to_string();
+        format!(
+            "x {}, y {}, heading {}\npen {}, colour {}, opacity {}\nturtle {}, {} saved\n",
+            number(&self.xcor),
+            number(&self.ycor),
+            number(&self.heading),
+            if self.is_pen_down == "TRUE" { "down" } else { "up" },
+            number(&self.color),
+            number(&self.pen_alpha),
+            if self.is_turtle_shown == "TRUE" {
+                "shown"
+            } else {
+                "hidden"
+            },
+            self.turtle_stack.len()
+        )
+    }
+
+    /// Restore the most recently saved turtle
+    pub fn pop_turtle(&mut self) -> Result<(), String> {
+        let state = self
+            .turtle_stack
+            .pop()
+            .ok_or_else(|| "POPTURTLE: turtle stack is empty".to_string())?;
+        self.xcor = state.xcor;
+        self.ycor = state.ycor;
+        self.heading = state.heading;
+        self.color = state.color;
+        self.is_pen_down = state.is_pen_down;
+        self.pen_alpha = state.pen_alpha;
+        self.fill_alpha = state.fill_alpha;
+        Ok(())
+    }
 }
 
 /// Extract commands to my enum
-/// so that they can be easily interpreted by the program
+/// so that they can be easily interpreted by the program.
+/// Every line of the file is passed in, so each statement
+/// knows its line number; empty lines and comments are skipped.
 pub fn extract_commands(
   lines: &Vec<&str>,
   start: usize,
   dummy_procedures: &mut HashMap<String, DummyProcedure>,
-) -> Result<(Vec<Command>, usize), String> {
-  let mut commands: Vec<Command> = Vec::new();
+) -> Result<(Vec<Statement>, usize), String> {
+  let mut commands: Vec<Statement> = Vec::new();
   let mut i = start;
 
   if lines.contains(&"[") && !lines.contains(&"]") {
@@ -45,6 +401,11 @@ pub fn extract_commands(
   }
 
   while i < lines.len() {
+      if lines[i].starts_with("// ") || lines[i].is_empty() {
+          i += 1;
+          continue;
+      }
+      let line = i + 1;
       let parts: Vec<&str> = lines
           .get(i)
           .map(|line| line.split_whitespace())
@@ -62,7 +423,10 @@ pub fn extract_commands(
               i = new_index;
               let operations = extract_operations(&raw_operations)?;
 
-              commands.push(Command::If(operations, block_commands));
+              commands.push(Statement {
+                  line,
+                  command: Command::If(operations, block_commands),
+              });
           }
           Some(&"WHILE") => {
               if parts[parts.len() - 1] != "[" {
@@ -74,7 +438,10 @@ pub fn extract_commands(
               i = new_index;
               let operations = extract_operations(&raw_operations)?;
 
-              commands.push(Command::Whlie(operations, block_commands));
+              commands.push(Statement {
+                  line,
+                  command: Command::Whlie(operations, block_commands),
+              });
           }
           Some(&"]") => {
               return Ok((commands, i));
@@ -102,7 +469,7 @@ pub fn extract_commands(
               return Ok((commands, i));
           }
           _ => match parse_command(lines[i], dummy_procedures) {
-              Ok(cmd) => commands.push(cmd),
+              Ok(command) => commands.push(Statement { line, command }),
               Err(e) => return Err(e),
           },
       }
@@ -119,8 +486,12 @@ pub fn extract_operations(operations: &[&str]) -> Result<Operation, String> {
   for operation in operations.iter().rev() {
       match *operation {
           "+" | "-" | "*" | "/" | "EQ" | "NE" | "AND" | "OR" | "GT" | "LT" => {
-              let left = stack.pop().expect("Missing left operand");
-              let right = stack.pop().expect("Missing right operand");
+              let left = stack
+                  .pop()
+                  .ok_or_else(|| format!("{} is missing its first value", operation))?;
+              let right = stack
+                  .pop()
+                  .ok_or_else(|| format!("{} is missing its second value", operation))?;
               let op = match *operation {
                   "+" => Operation::Add(Box::new(left), Box::new(right)),
                   "-" => Operation::Subtract(Box::new(left), Box::new(right)),
@@ -157,4 +528,16 @@ pub fn extract_operations(operations: &[&str]) -> Result<Operation, String> {
   } else {
       Err("There are still some values in the stack".to_string())
   }
-}
\ No newline at end of file
+}
+
+/// Number of tokens taken by the prefix expression at the start of `operations`
+pub fn operation_length(operations: &[&str]) -> Option<usize> {
+  match *operations.first()? {
+      "+" | "-" | "*" | "/" | "EQ" | "NE" | "AND" | "OR" | "GT" | "LT" => {
+          let left = operation_length(&operations[1..])?;
+          let right = operation_length(&operations[1 + left..])?;
+          Some(1 + left + right)
+      }
+      _ => Some(1),
+  }
+}
@@ -0,0 +1,78 @@
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Write a program to a file of its own, named after the test
+fn program(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rslogo-{}-{}.lg", name, std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+/// Run rslogo and read the display list it prints
+fn emit_json(args: &[&str]) -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_rslogo"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+const SOURCE: &str = "PENDOWN\nFORWARD \"10\nTURN \"90\nSHOWTURTLE\nFORWARD \"5\n";
+
+#[test]
+fn emit_json_without_an_image_path() {
+    let path = program("emit-json", SOURCE);
+    let list = emit_json(&[path.to_str().unwrap(), "--emit", "json", "100", "100"]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(list["width"], 100);
+    assert_eq!(list["height"], 100);
+    let items = list["items"].as_array().unwrap();
+    // two lines, then the turtle drawn where it stopped
+    let types: Vec<&str> = items
+        .iter()
+        .map(|item| item["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["segment", "segment", "fill"]);
+
+    assert_eq!(items[0]["from"], json!([50.0, 50.0]));
+    assert_eq!(items[0]["to"], json!([50.0, 40.0]));
+    assert_eq!(
+        items[0]["pen"],
+        json!({ "color": "#ffffff", "width": 1.0, "opacity": 1.0 })
+    );
+    assert_eq!(
+        items[0]["turtle"],
+        json!({ "x": 50.0, "y": 40.0, "heading": 0.0, "pen_down": true, "shown": false })
+    );
+    assert_eq!(
+        items[1]["turtle"],
+        json!({ "x": 55.0, "y": 40.0, "heading": 90.0, "pen_down": true, "shown": true })
+    );
+}
+
+#[test]
+fn emit_json_alongside_an_image() {
+    let path = program("emit-json-image", SOURCE);
+    let image = path.with_extension("svg");
+    let list = emit_json(&[
+        "--emit",
+        "json",
+        path.to_str().unwrap(),
+        image.to_str().unwrap(),
+        "100",
+        "100",
+    ]);
+    let svg = std::fs::read_to_string(&image).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&image).unwrap();
+
+    assert_eq!(list["items"].as_array().unwrap().len(), 3);
+    assert!(svg.contains("<path"));
+}